@@ -0,0 +1,1371 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use shared::math::pure_pursuit::PathCommand;
+use shared::math::{CarPosition, Point};
+use tracing::{debug, info};
+
+use crate::brain::{
+    find_free_slots, get_offset_target, get_steering_to, is_dotted_ahead, plan_entry, plan_exit,
+    Collision, LaneOffset, OvertakingConfig, ParkingConfig, ParkingManoeuvre, TrafficLightAhead,
+    TrafficLightConfig, WHEELBASE,
+};
+use crate::serial::camera::Signs;
+use crate::serial::Message;
+use crate::server::data::TrafficLightColor;
+use crate::track::{Tag, Track, TrackNode};
+
+/// Signs detected with a lower confidence are ignored
+pub const SIGN_CONFIDENCE: f64 = 0.5;
+/// How long a detected sign is taken into account
+const SIGN_MEMORY: Duration = Duration::from_secs(5);
+/// Without a crosswalk on the map, how long the car stays careful after seeing its sign
+const CROSSWALK_SIGN_DURATION: Duration = Duration::from_secs(4);
+/// In degrees
+const MAX_STEERING_ANGLE: f64 = 25.0;
+
+/// Speeds are in meters per second and distances in meters
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BehaviourConfig {
+    pub city_speed: f64,
+    pub highway_speed: f64,
+    pub roundabout_speed: f64,
+    /// Speed when getting close to an intersection or crosswalk
+    pub approach_speed: f64,
+    pub parking_search_speed: f64,
+    /// Distance at which the car starts preparing for an intersection or crosswalk
+    pub approach_distance: f64,
+    /// The car is considered at the stop line when it is closer than this
+    pub stop_line_distance: f64,
+    /// The car stops when an obstacle in its lane is closer than this
+    pub follow_distance: f64,
+    /// How long the car stays at a stop sign, in seconds
+    pub stop_duration: f64,
+    /// The car stops when it would hit a moving obstacle sooner than this, in seconds
+    pub collision_stop_time: f64,
+    /// The car slows down when it would hit a moving obstacle sooner than this, in seconds
+    pub collision_slow_time: f64,
+    pub parking: ParkingConfig,
+    pub overtaking: OvertakingConfig,
+    pub traffic_lights: TrafficLightConfig,
+}
+
+impl Default for BehaviourConfig {
+    fn default() -> Self {
+        Self {
+            city_speed: 0.3,
+            highway_speed: 0.5,
+            roundabout_speed: 0.2,
+            approach_speed: 0.15,
+            parking_search_speed: 0.15,
+            approach_distance: 0.6,
+            stop_line_distance: 0.1,
+            follow_distance: 0.5,
+            stop_duration: 3.0,
+            collision_stop_time: 1.5,
+            collision_slow_time: 3.0,
+            parking: ParkingConfig::default(),
+            overtaking: OvertakingConfig::default(),
+            traffic_lights: TrafficLightConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntersectionPhase {
+    Approaching,
+    /// Stopped at the stop line until the traffic light turns green
+    WaitingForGreen,
+    Crossing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopSignPhase {
+    Approaching,
+    Stopped { since: Instant },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrosswalkPhase {
+    Approaching,
+    /// Stopped until the pedestrian has crossed
+    Yielding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParkingPhase {
+    /// Driving slowly along the parking zone, looking for a free spot
+    Searching,
+    /// Driving the manoeuvre into the chosen spot
+    Entering,
+    Parked {
+        since: Instant,
+    },
+    /// Driving the manoeuvre back to the road
+    Leaving,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OvertakingPhase {
+    /// Stopped behind the obstacle
+    Waiting { since: Instant },
+    /// Moving to the opposite lane
+    ChangingLane,
+    /// Driving in the opposite lane next to the obstacle
+    Passing,
+    /// Moving back to the car's lane, after passing the obstacle or to abort
+    Returning,
+}
+
+/**
+ * What the car is currently doing. Manoeuvres keep the index in the route of the node
+ * they are about (e.g. the intersection entry), so they know when they are over.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behaviour {
+    LaneFollowing,
+    Highway,
+    Roundabout,
+    /// Crossing the next intersection without stopping, after a priority sign
+    PriorityRoad {
+        entry: usize,
+    },
+    Intersection {
+        entry: usize,
+        phase: IntersectionPhase,
+    },
+    StopSign {
+        entry: usize,
+        phase: StopSignPhase,
+    },
+    Crosswalk {
+        index: Option<usize>,
+        since: Instant,
+        phase: CrosswalkPhase,
+    },
+    Parking(ParkingPhase),
+    Overtaking(OvertakingPhase),
+    /// The mission is over or the car does not know where it is
+    Stopped,
+}
+
+impl Behaviour {
+    /// Behaviours in which the car simply follows the road
+    pub fn is_cruising(&self) -> bool {
+        matches!(
+            self,
+            Behaviour::LaneFollowing | Behaviour::Highway | Behaviour::Roundabout
+        )
+    }
+}
+
+impl Display for Behaviour {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Behaviour::Intersection { entry, phase } => {
+                write!(f, "Intersection({entry}, {phase:?})")
+            }
+            Behaviour::StopSign { entry, phase } => match phase {
+                StopSignPhase::Approaching => write!(f, "StopSign({entry}, Approaching)"),
+                StopSignPhase::Stopped { .. } => write!(f, "StopSign({entry}, Stopped)"),
+            },
+            Behaviour::Crosswalk { index, phase, .. } => {
+                write!(f, "Crosswalk({index:?}, {phase:?})")
+            }
+            Behaviour::PriorityRoad { entry } => write!(f, "PriorityRoad({entry})"),
+            Behaviour::Parking(ParkingPhase::Parked { .. }) => write!(f, "Parking(Parked)"),
+            Behaviour::Overtaking(OvertakingPhase::Waiting { .. }) => {
+                write!(f, "Overtaking(Waiting)")
+            }
+            behaviour => write!(f, "{behaviour:?}"),
+        }
+    }
+}
+
+/// What the car should do this tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Speed in meters per second and steering angle in degrees, positive to the left
+    Drive {
+        speed: f32,
+        steering: f32,
+    },
+    Brake {
+        steering: f32,
+    },
+}
+
+impl Command {
+    pub fn get_messages(&self) -> Vec<Message> {
+        match *self {
+            Command::Drive { speed, steering } => {
+                vec![Message::Steer(steering), Message::Speed(speed)]
+            }
+            Command::Brake { steering } => vec![Message::Brake(steering)],
+        }
+    }
+}
+
+/// Something in the car's lane, ahead of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObstacleAhead {
+    /// In meters
+    pub distance: f64,
+    /// Along the road, in meters per second
+    pub speed: f64,
+}
+
+/// Everything the car knows about its surroundings when taking a decision
+#[derive(Debug, Clone)]
+pub struct Perception<'a> {
+    pub now: Instant,
+    /// In meters, in the track's frame
+    pub position: &'a CarPosition,
+    /// Current speed of the car, in meters per second
+    pub speed: f64,
+    /// Output of the path follower for the planned route
+    pub path: PathCommand,
+    /// The route has been driven to its end
+    pub finished: bool,
+    pub route: &'a [&'a TrackNode],
+    /// Index in `route` of the last node the car passed
+    pub route_index: usize,
+    pub signs: &'a Signs,
+    pub traffic_light: Option<TrafficLightAhead>,
+    pub obstacle_ahead: Option<ObstacleAhead>,
+    /// A vehicle is coming in the opposite lane
+    pub oncoming_traffic: bool,
+    pub pedestrian_ahead: bool,
+    /// First predicted collision with a moving obstacle
+    pub collision: Option<Collision>,
+    /// Cars parked on the side of the road, in the track's frame
+    pub parked_cars: &'a [Point],
+}
+
+impl<'a> Perception<'a> {
+    /**
+     * Distance the car still has to drive along the route to reach `route[index]`, in meters.
+     * Nodes that were already passed are at a distance of 0.
+     */
+    pub fn get_distance_to(&self, index: usize) -> f64 {
+        if index <= self.route_index || index >= self.route.len() {
+            return 0.0;
+        }
+
+        let next = self.route[self.route_index + 1];
+        let to_next = (next.get_x() as f64 / 100.0 - self.position.x)
+            .hypot(next.get_y() as f64 / 100.0 - self.position.y);
+        let after_next: f32 = self.route[self.route_index + 1..=index]
+            .windows(2)
+            .map(|pair| pair[0].distance_to(pair[1]))
+            .sum();
+
+        to_next + after_next as f64 / 100.0
+    }
+
+    /**
+     * Distance from the start of the route to the projection of the car on its current edge,
+     * in meters, so that it does not change when the car moves sideways.
+     */
+    pub fn get_progress(&self) -> f64 {
+        let before: f32 = self
+            .route
+            .get(..=self.route_index)
+            .unwrap_or(self.route)
+            .windows(2)
+            .map(|pair| pair[0].distance_to(pair[1]))
+            .sum();
+        let Some((source, target)) = self
+            .route
+            .get(self.route_index)
+            .zip(self.route.get(self.route_index + 1))
+        else {
+            return before as f64 / 100.0;
+        };
+
+        let (start, end) = (Point::from(*source), Point::from(*target));
+        let length = start.distance_to(end);
+        let along = if length > 0.0 {
+            ((self.position.x - start.x) * (end.x - start.x)
+                + (self.position.y - start.y) * (end.y - start.y))
+                / length
+        } else {
+            0.0
+        };
+
+        before as f64 / 100.0 + along.clamp(0.0, length)
+    }
+
+    /// The edge of the route the car is driving on
+    fn get_current_edge(&self) -> Option<(usize, usize)> {
+        let source = self.route.get(self.route_index)?;
+        let target = self.route.get(self.route_index + 1)?;
+        Some((source.id, target.id))
+    }
+}
+
+/// When each kind of sign was last seen
+#[derive(Debug, Default, Clone)]
+struct SeenSigns {
+    stop: Option<Instant>,
+    priority: Option<Instant>,
+    crosswalk: Option<Instant>,
+    parking_start: Option<Instant>,
+    parking_stop: Option<Instant>,
+}
+
+/**
+ * Hierarchical state machine choosing the behaviour of the car: cruising states follow the road
+ * at the speed of its type, and hand over to manoeuvres (intersections, stop signs, crosswalks, ...)
+ * when the route or the camera tells that one is coming, going back to cruising once it is done.
+ */
+pub struct BehaviourMachine {
+    track: &'static Track,
+    config: BehaviourConfig,
+    behaviour: Behaviour,
+    seen_signs: SeenSigns,
+    parking_requested: bool,
+    /// Where the car was when it started looking for a parking spot
+    parking_zone: Option<CarPosition>,
+    /// Manoeuvre into or out of the parking spot
+    parking: Option<ParkingManoeuvre>,
+    /// Offset of the car to the left of its lane, while overtaking
+    lane_offset: LaneOffset,
+    /// Progress along the route at which the overtaken obstacle is behind the car
+    overtaking_end: f64,
+    /// Obstacle the car gave up waiting behind, in the track's frame, until it is taken
+    blocked_obstacle: Option<Point>,
+}
+
+fn is_recent(seen: Option<Instant>, now: Instant) -> bool {
+    seen.is_some_and(|seen| now.saturating_duration_since(seen) < SIGN_MEMORY)
+}
+
+impl BehaviourMachine {
+    pub fn new(track: &'static Track, config: BehaviourConfig) -> BehaviourMachine {
+        BehaviourMachine {
+            track,
+            behaviour: Behaviour::LaneFollowing,
+            seen_signs: SeenSigns::default(),
+            parking_requested: config.parking.enabled,
+            parking_zone: None,
+            parking: None,
+            lane_offset: LaneOffset::default(),
+            overtaking_end: 0.0,
+            blocked_obstacle: None,
+            config,
+        }
+    }
+
+    pub fn get_behaviour(&self) -> Behaviour {
+        self.behaviour
+    }
+
+    pub fn get_config(&self) -> &BehaviourConfig {
+        &self.config
+    }
+
+    /**
+     * The obstacle the car waited behind for too long, if any since the last call, so that
+     * the road can be blocked and the mission planned around it
+     */
+    pub fn take_blocked_obstacle(&mut self) -> Option<Point> {
+        self.blocked_obstacle.take()
+    }
+
+    fn transition(&mut self, next: Behaviour, reason: &str) {
+        if next != self.behaviour {
+            info!("Behaviour {} -> {next} ({reason})", self.behaviour);
+            self.behaviour = next;
+        }
+    }
+
+    /// Stops the car, e.g. because its position is unknown
+    pub fn stop(&mut self, reason: &str) -> Command {
+        self.transition(Behaviour::Stopped, reason);
+        Command::Brake { steering: 0.0 }
+    }
+
+    fn remember_signs(&mut self, signs: &Signs, now: Instant) {
+        let seen = &mut self.seen_signs;
+        for (confidence, last_seen) in [
+            (signs.stop, &mut seen.stop),
+            (signs.priority, &mut seen.priority),
+            (signs.crosswalk, &mut seen.crosswalk),
+            (signs.parking_start, &mut seen.parking_start),
+            (signs.parking_stop, &mut seen.parking_stop),
+        ] {
+            if confidence >= SIGN_CONFIDENCE {
+                *last_seen = Some(now);
+            }
+        }
+    }
+
+    /// Speed of the cruising behaviour matching the road the car is on
+    fn get_road_behaviour(&self, perception: &Perception) -> Behaviour {
+        match perception.get_current_edge() {
+            Some((source, target)) if self.track.edge_has_tag(source, target, Tag::Highway) => {
+                Behaviour::Highway
+            }
+            Some((source, target)) if self.track.edge_has_tag(source, target, Tag::Roundabout) => {
+                Behaviour::Roundabout
+            }
+            // Past the end of the route, the road is found from where the car is
+            None if self.track.is_on_highway(
+                perception.position.x as f32 * 100.0,
+                perception.position.y as f32 * 100.0,
+                perception.position.angle as f32,
+            ) =>
+            {
+                Behaviour::Highway
+            }
+            _ => Behaviour::LaneFollowing,
+        }
+    }
+
+    /// Whether the node of the route at `index` is close enough to prepare for it
+    fn is_approaching(&self, perception: &Perception, index: usize) -> bool {
+        perception.get_distance_to(index) <= self.config.approach_distance
+    }
+
+    /// Index of the next node of the route with the tag, if it is close enough to prepare for it
+    fn find_tag_ahead(&self, perception: &Perception, tag: Tag) -> Option<usize> {
+        self.track
+            .find_next_tag(perception.route, perception.route_index + 1, tag)
+            .filter(|&index| self.is_approaching(perception, index))
+    }
+
+    fn is_obstacle_close(&self, perception: &Perception) -> bool {
+        perception
+            .obstacle_ahead
+            .is_some_and(|obstacle| obstacle.distance <= self.config.follow_distance)
+    }
+
+    /// Manoeuvre to start from a cruising behaviour, if any
+    fn find_manoeuvre(&self, perception: &Perception) -> Option<(Behaviour, &'static str)> {
+        let now = perception.now;
+
+        if self.is_obstacle_close(perception) {
+            return Some((
+                Behaviour::Overtaking(OvertakingPhase::Waiting { since: now }),
+                "obstacle ahead",
+            ));
+        }
+
+        let entry = self
+            .track
+            .find_next_intersection(perception.route, perception.route_index + 1)
+            .filter(|&index| self.is_approaching(perception, index))
+            .or_else(|| self.find_tag_ahead(perception, Tag::StopLine));
+        if let Some(entry) = entry {
+            let has_light = perception
+                .traffic_light
+                .is_some_and(|light| light.index == entry);
+            return Some(if is_recent(self.seen_signs.stop, now) {
+                (
+                    Behaviour::StopSign {
+                        entry,
+                        phase: StopSignPhase::Approaching,
+                    },
+                    "stop sign before the intersection",
+                )
+            } else if is_recent(self.seen_signs.priority, now) && !has_light {
+                (Behaviour::PriorityRoad { entry }, "priority sign")
+            } else {
+                (
+                    Behaviour::Intersection {
+                        entry,
+                        phase: IntersectionPhase::Approaching,
+                    },
+                    "intersection ahead",
+                )
+            });
+        }
+
+        let crosswalk = self.find_tag_ahead(perception, Tag::Crosswalk);
+        if crosswalk.is_some() || is_recent(self.seen_signs.crosswalk, now) {
+            return Some((
+                Behaviour::Crosswalk {
+                    index: crosswalk,
+                    since: now,
+                    phase: CrosswalkPhase::Approaching,
+                },
+                "crosswalk ahead",
+            ));
+        }
+
+        let in_parking_zone = perception
+            .get_current_edge()
+            .is_some_and(|(source, target)| {
+                self.track.edge_has_tag(source, target, Tag::ParkingZone)
+            })
+            || self.find_tag_ahead(perception, Tag::ParkingZone).is_some();
+        if self.parking_requested
+            && (in_parking_zone || is_recent(self.seen_signs.parking_start, now))
+        {
+            return Some((Behaviour::Parking(ParkingPhase::Searching), "parking zone"));
+        }
+
+        None
+    }
+
+    /// Next behaviour and the reason for the change, if the current behaviour is over
+    fn get_next_behaviour(&mut self, perception: &Perception) -> Option<(Behaviour, &'static str)> {
+        let now = perception.now;
+        let is_passed = |index: usize| perception.route_index > index;
+        let at_stop_line =
+            |entry: usize| perception.get_distance_to(entry) <= self.config.stop_line_distance;
+        let light_color = |entry: usize| {
+            perception
+                .traffic_light
+                .filter(|light| light.index == entry)
+                .map(|light| light.color)
+        };
+        let must_stop = |entry: usize| {
+            light_color(entry).is_some()
+                && perception.must_stop_at_light(&self.config.traffic_lights)
+        };
+
+        if perception.finished {
+            return Some((Behaviour::Stopped, "route finished"));
+        }
+
+        match self.behaviour {
+            Behaviour::Stopped => Some((self.get_road_behaviour(perception), "route resumed")),
+            behaviour if behaviour.is_cruising() => self.find_manoeuvre(perception).or_else(|| {
+                let road = self.get_road_behaviour(perception);
+                (road != behaviour).then_some((road, "road type changed"))
+            }),
+            Behaviour::PriorityRoad { entry } => is_passed(entry)
+                .then(|| (self.get_road_behaviour(perception), "intersection crossed")),
+            Behaviour::Intersection { entry, phase } => match phase {
+                IntersectionPhase::Approaching if at_stop_line(entry) || is_passed(entry) => {
+                    Some(if must_stop(entry) {
+                        (
+                            Behaviour::Intersection {
+                                entry,
+                                phase: IntersectionPhase::WaitingForGreen,
+                            },
+                            "red light",
+                        )
+                    } else {
+                        (
+                            Behaviour::Intersection {
+                                entry,
+                                phase: IntersectionPhase::Crossing,
+                            },
+                            "intersection is free",
+                        )
+                    })
+                }
+                IntersectionPhase::Approaching
+                    if light_color(entry) == Some(TrafficLightColor::Yellow)
+                        && !must_stop(entry) =>
+                {
+                    Some((
+                        Behaviour::Intersection {
+                            entry,
+                            phase: IntersectionPhase::Crossing,
+                        },
+                        "too close to stop on yellow",
+                    ))
+                }
+                IntersectionPhase::WaitingForGreen if !must_stop(entry) => Some((
+                    Behaviour::Intersection {
+                        entry,
+                        phase: IntersectionPhase::Crossing,
+                    },
+                    "green light",
+                )),
+                // The intersection is over once its middle node is passed too
+                IntersectionPhase::Crossing if is_passed(entry + 1) => {
+                    Some((self.get_road_behaviour(perception), "intersection crossed"))
+                }
+                _ => None,
+            },
+            Behaviour::StopSign { entry, phase } => match phase {
+                StopSignPhase::Approaching if at_stop_line(entry) || is_passed(entry) => Some((
+                    Behaviour::StopSign {
+                        entry,
+                        phase: StopSignPhase::Stopped { since: now },
+                    },
+                    "at the stop line",
+                )),
+                StopSignPhase::Stopped { since }
+                    if now.saturating_duration_since(since).as_secs_f64()
+                        >= self.config.stop_duration =>
+                {
+                    self.seen_signs.stop = None;
+                    Some((
+                        Behaviour::Intersection {
+                            entry,
+                            phase: IntersectionPhase::Approaching,
+                        },
+                        "stopped long enough",
+                    ))
+                }
+                _ => None,
+            },
+            Behaviour::Crosswalk {
+                index,
+                since,
+                phase,
+            } => {
+                let is_over = match index {
+                    Some(index) => is_passed(index + 1),
+                    None => now.saturating_duration_since(since) >= CROSSWALK_SIGN_DURATION,
+                };
+
+                if is_over {
+                    self.seen_signs.crosswalk = None;
+                    return Some((self.get_road_behaviour(perception), "crosswalk passed"));
+                }
+
+                match (phase, perception.pedestrian_ahead) {
+                    (CrosswalkPhase::Approaching, true) => Some((
+                        Behaviour::Crosswalk {
+                            index,
+                            since,
+                            phase: CrosswalkPhase::Yielding,
+                        },
+                        "pedestrian crossing",
+                    )),
+                    (CrosswalkPhase::Yielding, false) => Some((
+                        Behaviour::Crosswalk {
+                            index,
+                            since,
+                            phase: CrosswalkPhase::Approaching,
+                        },
+                        "pedestrian crossed",
+                    )),
+                    _ => None,
+                }
+            }
+            Behaviour::Parking(phase) => self.get_next_parking_phase(perception, phase),
+            Behaviour::Overtaking(phase) => self.get_next_overtaking_phase(perception, phase),
+            _ => None,
+        }
+    }
+
+    /// Finds a free spot, parks in it, waits and drives back to the road
+    fn get_next_parking_phase(
+        &mut self,
+        perception: &Perception,
+        phase: ParkingPhase,
+    ) -> Option<(Behaviour, &'static str)> {
+        let now = perception.now;
+        let position = perception.position;
+        let config = &self.config.parking;
+
+        match phase {
+            ParkingPhase::Searching => {
+                let zone = self.parking_zone.get_or_insert_with(|| position.clone());
+                // How far the car drove along the parking zone
+                let (sin, cos) = zone.angle.sin_cos();
+                let progress = (position.x - zone.x) * cos + (position.y - zone.y) * sin;
+                let zone_length =
+                    config.slot_offset + config.slot_count as f64 * config.slot_length;
+
+                let slot = find_free_slots(zone, config, perception.parked_cars)
+                    .into_iter()
+                    .find(|slot| progress >= slot.distance);
+                if let Some(slot) = slot {
+                    info!("Parking in spot {} of the zone", slot.index);
+                    let entry = plan_entry(position, &slot, config);
+                    self.parking = Some(ParkingManoeuvre::new(entry, position));
+                    return Some((
+                        Behaviour::Parking(ParkingPhase::Entering),
+                        "free parking spot",
+                    ));
+                }
+
+                let reason = if is_recent(self.seen_signs.parking_stop, now) {
+                    "end of the parking zone"
+                } else if progress > zone_length {
+                    "no free parking spot"
+                } else {
+                    return None;
+                };
+                self.parking_requested = false;
+                self.parking_zone = None;
+                Some((self.get_road_behaviour(perception), reason))
+            }
+            ParkingPhase::Entering => self
+                .parking
+                .as_ref()
+                .is_none_or(ParkingManoeuvre::is_finished)
+                .then_some((
+                    Behaviour::Parking(ParkingPhase::Parked { since: now }),
+                    "parked",
+                )),
+            ParkingPhase::Parked { since }
+                if now.saturating_duration_since(since).as_secs_f64() >= config.parked_duration =>
+            {
+                let exit = plan_exit(self.parking.as_ref()?.get_steps());
+                self.parking = Some(ParkingManoeuvre::new(exit, position));
+                Some((
+                    Behaviour::Parking(ParkingPhase::Leaving),
+                    "parked long enough",
+                ))
+            }
+            ParkingPhase::Parked { .. } => None,
+            ParkingPhase::Leaving => {
+                if !self
+                    .parking
+                    .as_ref()
+                    .is_none_or(ParkingManoeuvre::is_finished)
+                {
+                    return None;
+                }
+                self.parking_requested = false;
+                self.parking_zone = None;
+                self.parking = None;
+                Some((self.get_road_behaviour(perception), "left the parking spot"))
+            }
+        }
+    }
+
+    /**
+     * Waits behind the obstacle until it leaves or can be overtaken, then passes it in the
+     * opposite lane. Lane changes are only allowed on dotted edges, and the car goes back
+     * to its lane if traffic comes in the opposite one before it left its own.
+     */
+    fn get_next_overtaking_phase(
+        &mut self,
+        perception: &Perception,
+        phase: OvertakingPhase,
+    ) -> Option<(Behaviour, &'static str)> {
+        let config = &self.config.overtaking;
+        let progress = perception.get_progress();
+        let returning = |from: f64| LaneOffset {
+            from,
+            to: 0.0,
+            start: progress,
+            length: config.lane_change_distance * from / config.lane_width,
+        };
+
+        match phase {
+            OvertakingPhase::Waiting { since } => {
+                let Some(obstacle) = perception
+                    .obstacle_ahead
+                    .filter(|_| self.is_obstacle_close(perception))
+                else {
+                    return Some((self.get_road_behaviour(perception), "obstacle gone"));
+                };
+
+                let distance = config.get_overtaking_distance(obstacle.distance);
+                let can_overtake = obstacle.speed <= config.max_obstacle_speed
+                    && !perception.oncoming_traffic
+                    && is_dotted_ahead(
+                        self.track,
+                        perception.route,
+                        perception.route_index,
+                        perception.position,
+                        distance,
+                    );
+                if !can_overtake {
+                    let waited = perception.now.saturating_duration_since(since);
+                    if waited.as_secs_f64() < config.max_wait {
+                        return None;
+                    }
+
+                    let (sin, cos) = perception.position.angle.sin_cos();
+                    self.blocked_obstacle = Some(Point::new(
+                        perception.position.x + obstacle.distance * cos,
+                        perception.position.y + obstacle.distance * sin,
+                    ));
+                    return Some((
+                        Behaviour::Overtaking(OvertakingPhase::Waiting {
+                            since: perception.now,
+                        }),
+                        "lane blocked, planning another route",
+                    ));
+                }
+
+                self.lane_offset = LaneOffset {
+                    from: 0.0,
+                    to: config.lane_width,
+                    start: progress,
+                    length: config.lane_change_distance,
+                };
+                self.overtaking_end = progress + distance - config.lane_change_distance;
+                Some((
+                    Behaviour::Overtaking(OvertakingPhase::ChangingLane),
+                    "overtaking a slow obstacle",
+                ))
+            }
+            OvertakingPhase::ChangingLane if perception.oncoming_traffic => {
+                self.lane_offset = returning(self.lane_offset.get(progress));
+                Some((
+                    Behaviour::Overtaking(OvertakingPhase::Returning),
+                    "opposite lane occupied",
+                ))
+            }
+            OvertakingPhase::ChangingLane => self.lane_offset.is_done(progress).then_some((
+                Behaviour::Overtaking(OvertakingPhase::Passing),
+                "in the opposite lane",
+            )),
+            OvertakingPhase::Passing if progress >= self.overtaking_end => {
+                self.lane_offset = returning(config.lane_width);
+                Some((
+                    Behaviour::Overtaking(OvertakingPhase::Returning),
+                    "obstacle passed",
+                ))
+            }
+            OvertakingPhase::Passing => None,
+            OvertakingPhase::Returning => {
+                if !self.lane_offset.is_done(progress) {
+                    return None;
+                }
+                self.lane_offset = LaneOffset::default();
+                Some(if self.is_obstacle_close(perception) {
+                    (
+                        Behaviour::Overtaking(OvertakingPhase::Waiting {
+                            since: perception.now,
+                        }),
+                        "obstacle still ahead",
+                    )
+                } else {
+                    (self.get_road_behaviour(perception), "back in the lane")
+                })
+            }
+        }
+    }
+
+    /// Follows the route shifted by the current lane offset
+    fn get_overtaking_command(&self, perception: &Perception) -> Command {
+        let config = &self.config.overtaking;
+        // The offset where the target is, so the car starts turning before the lane change
+        let offset = self
+            .lane_offset
+            .get(perception.get_progress() + config.lookahead);
+        let Some(target) = get_offset_target(
+            perception.route,
+            perception.route_index,
+            perception.position,
+            config.lookahead,
+            offset,
+        ) else {
+            return Command::Brake { steering: 0.0 };
+        };
+
+        let steering = get_steering_to(perception.position, target, WHEELBASE)
+            .to_degrees()
+            .clamp(-MAX_STEERING_ANGLE, MAX_STEERING_ANGLE);
+        Command::Drive {
+            speed: perception.path.speed.min(config.speed) as f32,
+            steering: steering as f32,
+        }
+    }
+
+    fn get_command(&mut self, perception: &Perception) -> Command {
+        let steering = perception.path.steering_angle.to_degrees() as f32;
+        let limit = |speed: f64| Command::Drive {
+            speed: perception.path.speed.min(speed) as f32,
+            steering,
+        };
+        let brake = Command::Brake { steering };
+
+        match self.behaviour {
+            Behaviour::LaneFollowing | Behaviour::PriorityRoad { .. } => {
+                limit(self.config.city_speed)
+            }
+            Behaviour::Highway => limit(self.config.highway_speed),
+            Behaviour::Roundabout => limit(self.config.roundabout_speed),
+            Behaviour::Intersection { phase, .. } => match phase {
+                IntersectionPhase::WaitingForGreen => brake,
+                _ => limit(self.config.approach_speed),
+            },
+            Behaviour::StopSign { phase, .. } => match phase {
+                StopSignPhase::Approaching => limit(self.config.approach_speed),
+                StopSignPhase::Stopped { .. } => brake,
+            },
+            Behaviour::Crosswalk { phase, .. } => match phase {
+                CrosswalkPhase::Approaching => limit(self.config.approach_speed),
+                CrosswalkPhase::Yielding => brake,
+            },
+            Behaviour::Parking(phase) => match phase {
+                ParkingPhase::Searching => limit(self.config.parking_search_speed),
+                ParkingPhase::Entering | ParkingPhase::Leaving => self
+                    .parking
+                    .as_mut()
+                    .and_then(|manoeuvre| manoeuvre.update(perception.position))
+                    .unwrap_or(Command::Brake { steering: 0.0 }),
+                ParkingPhase::Parked { .. } => Command::Brake { steering: 0.0 },
+            },
+            Behaviour::Overtaking(phase) => match phase {
+                OvertakingPhase::Waiting { .. } => brake,
+                _ => self.get_overtaking_command(perception),
+            },
+            Behaviour::Stopped => brake,
+        }
+    }
+
+    /**
+     * Updates the behaviour with what the car currently perceives and returns what it should do.
+     * Several transitions can happen in a single update, e.g. when a manoeuvre ends right
+     * where another one starts.
+     */
+    pub fn update(&mut self, perception: &Perception) -> Command {
+        self.remember_signs(perception.signs, perception.now);
+
+        // Bounded in case two behaviours would keep switching to each other
+        for _ in 0..4 {
+            match self.get_next_behaviour(perception) {
+                Some((next, reason)) if next != self.behaviour => self.transition(next, reason),
+                _ => break,
+            }
+        }
+
+        let command = self.get_command(perception);
+        self.avoid_collision(command, perception)
+    }
+
+    /// Slows down or waits when a moving obstacle is about to cross the route
+    fn avoid_collision(&self, command: Command, perception: &Perception) -> Command {
+        let (Some(collision), Command::Drive { speed, steering }) = (perception.collision, command)
+        else {
+            return command;
+        };
+        if speed <= 0.0 {
+            return command;
+        }
+
+        if collision.time < self.config.collision_stop_time {
+            debug!(
+                "Waiting for moving obstacle {} ({:.1} s)",
+                collision.id, collision.time
+            );
+            Command::Brake { steering }
+        } else if collision.time < self.config.collision_slow_time {
+            Command::Drive {
+                speed: speed.min(self.config.approach_speed as f32),
+                steering,
+            }
+        } else {
+            command
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{find_path, get_test_track, PathCosts};
+
+    use super::*;
+
+    struct Scenario {
+        route: Vec<&'static TrackNode>,
+        signs: Signs,
+        traffic_light: Option<TrafficLightAhead>,
+        speed: f64,
+        obstacle_ahead: Option<ObstacleAhead>,
+        oncoming_traffic: bool,
+        pedestrian_ahead: bool,
+        collision: Option<Collision>,
+        parked_cars: Vec<Point>,
+    }
+
+    impl Scenario {
+        /// Route going through the intersection entered at node 2
+        fn new() -> Scenario {
+            let track = get_test_track();
+            let route = find_path(
+                track,
+                track.get_node_by_id(56).unwrap(),
+                track.get_node_by_id(3).unwrap(),
+                &PathCosts::default(),
+            )
+            .unwrap()
+            .nodes;
+
+            Scenario::with_route(route)
+        }
+
+        fn with_route(route: Vec<&'static TrackNode>) -> Scenario {
+            Scenario {
+                route,
+                signs: Signs::default(),
+                traffic_light: None,
+                speed: 0.0,
+                obstacle_ahead: None,
+                oncoming_traffic: false,
+                pedestrian_ahead: false,
+                collision: None,
+                parked_cars: Vec::new(),
+            }
+        }
+
+        fn get_index(&self, id: usize) -> usize {
+            self.route.iter().position(|node| node.id == id).unwrap()
+        }
+
+        /// Sets the color of the light at the stop line of the node at `index` of the route
+        fn set_light(&mut self, index: usize, color: TrafficLightColor) {
+            self.traffic_light = Some(TrafficLightAhead {
+                id: 1,
+                index,
+                color,
+            });
+        }
+
+        /// Updates the machine with the car on the node at `index` of the route
+        fn update_at(&self, machine: &mut BehaviourMachine, index: usize, now: Instant) -> Command {
+            let node = self.route[index];
+            let position = CarPosition::new(
+                node.get_x() as f64 / 100.0,
+                node.get_y() as f64 / 100.0,
+                0.0,
+            );
+            self.update_with(machine, index, &position, now)
+        }
+
+        fn update_with(
+            &self,
+            machine: &mut BehaviourMachine,
+            index: usize,
+            position: &CarPosition,
+            now: Instant,
+        ) -> Command {
+            let perception = Perception {
+                now,
+                position,
+                speed: self.speed,
+                path: PathCommand {
+                    steering_angle: 0.0,
+                    speed: 0.5,
+                    closest_index: 0,
+                },
+                finished: false,
+                route: &self.route,
+                route_index: index,
+                signs: &self.signs,
+                traffic_light: self.traffic_light,
+                obstacle_ahead: self.obstacle_ahead,
+                oncoming_traffic: self.oncoming_traffic,
+                pedestrian_ahead: self.pedestrian_ahead,
+                collision: self.collision,
+                parked_cars: &self.parked_cars,
+            };
+
+            machine.update(&perception)
+        }
+    }
+
+    fn is_braking(command: Command) -> bool {
+        matches!(command, Command::Brake { .. })
+    }
+
+    fn is_waiting(behaviour: Behaviour) -> bool {
+        matches!(
+            behaviour,
+            Behaviour::Overtaking(OvertakingPhase::Waiting { .. })
+        )
+    }
+
+    #[test]
+    fn test_stop_sign() {
+        let mut scenario = Scenario::new();
+        let mut machine = BehaviourMachine::new(get_test_track(), BehaviourConfig::default());
+        let entry = scenario.get_index(2);
+        let now = Instant::now();
+
+        let command = scenario.update_at(&mut machine, 0, now);
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+        assert!(!is_braking(command));
+
+        scenario.signs.stop = 0.9;
+        scenario.update_at(&mut machine, entry - 1, now);
+        assert!(matches!(
+            machine.get_behaviour(),
+            Behaviour::StopSign {
+                phase: StopSignPhase::Approaching,
+                ..
+            }
+        ));
+
+        scenario.signs.stop = 0.0;
+        assert!(is_braking(scenario.update_at(&mut machine, entry, now)));
+        assert!(is_braking(scenario.update_at(
+            &mut machine,
+            entry,
+            now + Duration::from_secs(1)
+        )));
+
+        let later = now + Duration::from_secs_f64(BehaviourConfig::default().stop_duration);
+        assert!(!is_braking(scenario.update_at(&mut machine, entry, later)));
+        assert!(matches!(
+            machine.get_behaviour(),
+            Behaviour::Intersection {
+                phase: IntersectionPhase::Crossing,
+                ..
+            }
+        ));
+
+        scenario.update_at(&mut machine, entry + 2, later);
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+    }
+
+    #[test]
+    fn test_traffic_light() {
+        let mut scenario = Scenario::new();
+        let mut machine = BehaviourMachine::new(get_test_track(), BehaviourConfig::default());
+        let entry = scenario.get_index(2);
+        let now = Instant::now();
+
+        scenario.set_light(entry, TrafficLightColor::Red);
+        scenario.update_at(&mut machine, entry - 1, now);
+        assert!(is_braking(scenario.update_at(&mut machine, entry, now)));
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Intersection {
+                entry,
+                phase: IntersectionPhase::WaitingForGreen
+            }
+        );
+
+        scenario.set_light(entry, TrafficLightColor::Green);
+        assert!(!is_braking(scenario.update_at(&mut machine, entry, now)));
+    }
+
+    #[test]
+    fn test_yellow_light() {
+        let mut scenario = Scenario::new();
+        let entry = scenario.get_index(2);
+        let now = Instant::now();
+        scenario.set_light(entry, TrafficLightColor::Yellow);
+
+        // 20 cm before the stop line
+        let (before, line) = (scenario.route[entry - 1], scenario.route[entry]);
+        let ratio = 1.0 - 20.0 / before.distance_to(line) as f64;
+        let position = CarPosition::new(
+            (before.get_x() as f64 + ratio * (line.get_x() - before.get_x()) as f64) / 100.0,
+            (before.get_y() as f64 + ratio * (line.get_y() - before.get_y()) as f64) / 100.0,
+            0.0,
+        );
+
+        // Too fast to stop before the line
+        let mut machine = BehaviourMachine::new(get_test_track(), BehaviourConfig::default());
+        scenario.speed = 0.5;
+        scenario.update_with(&mut machine, entry - 1, &position, now);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Intersection {
+                entry,
+                phase: IntersectionPhase::Crossing
+            }
+        );
+
+        // Slow enough to stop, and then waits for the green light
+        let mut machine = BehaviourMachine::new(get_test_track(), BehaviourConfig::default());
+        scenario.speed = 0.15;
+        scenario.update_with(&mut machine, entry - 1, &position, now);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Intersection {
+                entry,
+                phase: IntersectionPhase::Approaching
+            }
+        );
+        scenario.speed = 0.0;
+        assert!(is_braking(scenario.update_at(&mut machine, entry, now)));
+    }
+
+    #[test]
+    fn test_obstacle_and_pedestrian() {
+        let mut scenario = Scenario::new();
+        let mut machine = BehaviourMachine::new(get_test_track(), BehaviourConfig::default());
+        let now = Instant::now();
+
+        scenario.obstacle_ahead = Some(ObstacleAhead {
+            distance: 0.3,
+            speed: 0.0,
+        });
+        assert!(is_braking(scenario.update_at(&mut machine, 0, now)));
+        scenario.obstacle_ahead = None;
+        assert!(!is_braking(scenario.update_at(&mut machine, 0, now)));
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+
+        scenario.signs.crosswalk = 0.8;
+        scenario.update_at(&mut machine, 0, now);
+        scenario.signs.crosswalk = 0.0;
+        scenario.pedestrian_ahead = true;
+        assert!(is_braking(scenario.update_at(&mut machine, 0, now)));
+        scenario.pedestrian_ahead = false;
+        assert!(!is_braking(scenario.update_at(&mut machine, 0, now)));
+
+        scenario.update_at(&mut machine, 0, now + CROSSWALK_SIGN_DURATION);
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+
+        scenario.collision = Some(Collision { id: 1, time: 2.0 });
+        let command = scenario.update_at(&mut machine, 0, now);
+        assert!(matches!(command, Command::Drive { speed, .. } if speed <= 0.15));
+        scenario.collision = Some(Collision { id: 1, time: 1.0 });
+        assert!(is_braking(scenario.update_at(&mut machine, 0, now)));
+    }
+
+    #[test]
+    fn test_parking() {
+        let mut scenario = Scenario::new();
+        let mut config = BehaviourConfig::default();
+        config.parking.enabled = true;
+        let mut machine = BehaviourMachine::new(get_test_track(), config.clone());
+        let now = Instant::now();
+        let at = |x: f64| CarPosition::new(x, 0.0, 0.0);
+
+        scenario.signs.parking_start = 0.9;
+        scenario.update_with(&mut machine, 0, &at(0.0), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Parking(ParkingPhase::Searching)
+        );
+        scenario.signs.parking_start = 0.0;
+
+        // The first spot is taken, so the car parks in the second one
+        let slots = find_free_slots(&at(0.0), &config.parking, &[]);
+        scenario.parked_cars = vec![slots[0].center];
+        scenario.update_with(&mut machine, 0, &at(slots[0].distance), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Parking(ParkingPhase::Searching)
+        );
+        let command = scenario.update_with(&mut machine, 0, &at(slots[1].distance), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Parking(ParkingPhase::Entering)
+        );
+        assert!(matches!(command, Command::Drive { speed, .. } if speed > 0.0));
+
+        // Teleporting far away drives every step of the manoeuvre at once
+        scenario.update_with(&mut machine, 0, &at(100.0), now);
+        scenario.update_with(&mut machine, 0, &at(100.0), now);
+        assert!(matches!(
+            machine.get_behaviour(),
+            Behaviour::Parking(ParkingPhase::Parked { .. })
+        ));
+
+        let later = now + Duration::from_secs_f64(config.parking.parked_duration);
+        let command = scenario.update_with(&mut machine, 0, &at(100.0), later);
+        assert_eq!(
+            machine.get_behaviour(),
+            Behaviour::Parking(ParkingPhase::Leaving)
+        );
+        assert!(!is_braking(command));
+
+        scenario.update_with(&mut machine, 0, &at(0.0), later);
+        scenario.update_with(&mut machine, 0, &at(0.0), later);
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+    }
+
+    #[test]
+    fn test_overtaking() {
+        let track = get_test_track();
+        // Straight road with dotted edges from node 32 to node 36
+        let route: Vec<&TrackNode> = (31..=37)
+            .map(|id| track.get_node_by_id(id).unwrap())
+            .collect();
+        let mut scenario = Scenario::with_route(route);
+        let mut config = BehaviourConfig::default();
+        config.overtaking.pass_distance = 0.2;
+        let mut machine = BehaviourMachine::new(track, config);
+        let now = Instant::now();
+        let start = Point::from(scenario.route[1]);
+        let at = |along: f64, left: f64| CarPosition::new(start.x + along, start.y + left, 0.0);
+        let overtaking = |phase| Behaviour::Overtaking(phase);
+
+        scenario.obstacle_ahead = Some(ObstacleAhead {
+            distance: 0.3,
+            speed: 0.0,
+        });
+        scenario.oncoming_traffic = true;
+        assert!(is_braking(scenario.update_with(
+            &mut machine,
+            1,
+            &at(0.0, 0.0),
+            now
+        )));
+        assert!(is_waiting(machine.get_behaviour()));
+
+        // Once the opposite lane is free, the car moves to it
+        scenario.oncoming_traffic = false;
+        let command = scenario.update_with(&mut machine, 1, &at(0.0, 0.0), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            overtaking(OvertakingPhase::ChangingLane)
+        );
+        assert!(matches!(command, Command::Drive { steering, .. } if steering > 0.0));
+
+        // Traffic comes in the opposite lane, so the car goes back behind the obstacle
+        scenario.oncoming_traffic = true;
+        let command = scenario.update_with(&mut machine, 1, &at(0.1, 0.05), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            overtaking(OvertakingPhase::Returning)
+        );
+        assert!(matches!(command, Command::Drive { steering, .. } if steering < 0.0));
+        scenario.obstacle_ahead.as_mut().unwrap().distance = 0.15;
+        scenario.update_with(&mut machine, 1, &at(0.2, 0.0), now);
+        assert!(is_waiting(machine.get_behaviour()));
+
+        scenario.oncoming_traffic = false;
+        scenario.update_with(&mut machine, 1, &at(0.2, 0.0), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            overtaking(OvertakingPhase::ChangingLane)
+        );
+        scenario.obstacle_ahead = None;
+        scenario.update_with(&mut machine, 3, &at(0.7, 0.37), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            overtaking(OvertakingPhase::Passing)
+        );
+        scenario.update_with(&mut machine, 4, &at(1.0, 0.37), now);
+        assert_eq!(
+            machine.get_behaviour(),
+            overtaking(OvertakingPhase::Returning)
+        );
+        scenario.update_with(&mut machine, 5, &at(1.4, 0.0), now);
+        assert_eq!(machine.get_behaviour(), Behaviour::LaneFollowing);
+    }
+
+    #[test]
+    fn test_overtaking_timeout() {
+        let track = get_test_track();
+        // Straight road whose first edge has a solid line
+        let route: Vec<&TrackNode> = (31..=37)
+            .map(|id| track.get_node_by_id(id).unwrap())
+            .collect();
+        let mut scenario = Scenario::with_route(route);
+        let config = BehaviourConfig::default();
+        let max_wait = Duration::from_secs_f64(config.overtaking.max_wait);
+        let mut machine = BehaviourMachine::new(track, config);
+        let now = Instant::now();
+
+        scenario.obstacle_ahead = Some(ObstacleAhead {
+            distance: 0.3,
+            speed: 0.0,
+        });
+        assert!(is_braking(scenario.update_at(&mut machine, 0, now)));
+        assert!(is_waiting(machine.get_behaviour()));
+
+        scenario.update_at(&mut machine, 0, now + max_wait / 2);
+        assert_eq!(machine.take_blocked_obstacle(), None);
+
+        // The car gives up and reports where the obstacle is, so that the road gets blocked
+        let later = now + max_wait;
+        assert!(is_braking(scenario.update_at(&mut machine, 0, later)));
+        assert!(is_waiting(machine.get_behaviour()));
+        let obstacle = machine.take_blocked_obstacle().unwrap();
+        let start = Point::from(scenario.route[0]);
+        assert!(obstacle.distance_to(Point::new(start.x + 0.3, start.y)) < 1e-6);
+        assert_eq!(machine.take_blocked_obstacle(), None);
+
+        // Then it waits as long again before giving up again
+        scenario.update_at(&mut machine, 0, later + max_wait / 2);
+        assert_eq!(machine.take_blocked_obstacle(), None);
+        scenario.update_at(&mut machine, 0, later + max_wait);
+        assert!(machine.take_blocked_obstacle().is_some());
+    }
+}
@@ -0,0 +1,696 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use shared::math::pure_pursuit::{PathCommand, PurePursuitController};
+use shared::math::{CarPosition, Point, Trajectory, TrajectoryConfig};
+use tracing::{debug, info, warn};
+
+use crate::serial::camera::{CameraData, CameraFrame, Signs};
+use crate::serial::Message;
+use crate::server::data::{EnvironmentalObstacle, ObstacleId, ServerCarPos, TrafficLightsStatus};
+use crate::server::ServerData;
+use crate::track::{
+    build_trajectory, project_on_segment, replan_mission, Blockages, Mission, PathCosts, Track,
+};
+
+pub use self::behaviour::*;
+pub use self::overtaking::*;
+pub use self::parking::*;
+pub use self::tracker::*;
+pub use self::traffic_lights::*;
+pub use self::vision::*;
+
+mod behaviour;
+mod overtaking;
+mod parking;
+mod tracker;
+mod traffic_lights;
+mod vision;
+
+/// Distance between the front and rear axles of the car, in meters
+const WHEELBASE: f64 = 0.26;
+/// Without a new position for this long, the car does not know where it is anymore and stops
+const POSITION_TIMEOUT: Duration = Duration::from_secs(1);
+/// The car has to move this much, in meters, before its heading is estimated again
+const MIN_HEADING_DISTANCE: f64 = 0.05;
+/// How many nodes of the route ahead of the car are considered when locating it on the route
+const ROUTE_LOOKAHEAD: usize = 4;
+/// Commands closer than this to the last sent one are not sent again
+const SPEED_TOLERANCE: f32 = 0.01;
+/// Without a new speed from the encoder for this long, the car assumes it drives at the commanded speed
+const ENCODER_TIMEOUT: Duration = Duration::from_millis(500);
+const STEERING_TOLERANCE: f32 = 0.5; // degrees
+
+/// Settings of the decision loop
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BrainConfig {
+    /// How many decisions are taken per second
+    pub decision_rate: f64,
+    /// Speed limit of the car, in meters per second
+    pub max_speed: f64,
+    /// The car is stopped when no decision was taken for this long, in seconds
+    pub watchdog_timeout: f64,
+    /// How much the car steers towards the lane seen by the camera rather than the planned trajectory, from 0 to 1
+    pub lane_weight: f64,
+    pub behaviour: BehaviourConfig,
+    pub tracker: TrackerConfig,
+}
+
+impl Default for BrainConfig {
+    fn default() -> Self {
+        Self {
+            decision_rate: 20.0,
+            max_speed: 0.5,
+            watchdog_timeout: 0.5,
+            lane_weight: 0.3,
+            behaviour: BehaviourConfig::default(),
+            tracker: TrackerConfig::default(),
+        }
+    }
+}
+
+impl BrainConfig {
+    pub fn get_period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.decision_rate)
+    }
+
+    pub fn get_watchdog_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.watchdog_timeout)
+    }
+}
+
+/**
+ * Decides what the car does, from the latest data received from the server and the camera.
+ * Positions are in meters, in the track's frame (y pointing up).
+ */
+pub struct Brain {
+    track: &'static Track,
+    track_height: f64,
+    mission: Option<Mission<'static>>,
+    trajectory: Trajectory,
+    trajectory_config: TrajectoryConfig,
+    costs: PathCosts,
+    /// Roads blocked by obstacles, which the mission avoids
+    blockages: Blockages,
+    controller: PurePursuitController,
+    lane_weight: f64,
+    behaviour: BehaviourMachine,
+    /// Index in the mission of the last node the car passed
+    route_index: usize,
+
+    position: Option<CarPosition>,
+    /// Where the car was when its heading was last estimated
+    heading_origin: Option<(f64, f64)>,
+    last_position_update: Option<Instant>,
+    /// Last speed measured by the encoder, in meters per second, and when it was received
+    measured_speed: Option<(Instant, f64)>,
+    /// Unknown until the traffic light server sends it
+    traffic_lights: Option<TrafficLightsStatus>,
+    moving_obstacles: ObstacleTracker,
+    signs: Signs,
+    vision: Vision,
+    /// Cars parked on the side of the road, in the track's frame
+    parked_cars: Vec<Point>,
+
+    /// Obstacles to tell the environment server about
+    reported_obstacles: Vec<EnvironmentalObstacle>,
+    /// Obstacles already reported, as (obstacle, closest node), so they are only reported once
+    known_obstacles: HashSet<(u8, usize)>,
+    /// Last command sent to the car
+    last_command: Option<Command>,
+}
+
+impl Brain {
+    pub fn new(
+        track: &'static Track,
+        track_height: f32,
+        mission: Option<Mission<'static>>,
+        costs: &PathCosts,
+        config: &BrainConfig,
+    ) -> Brain {
+        let trajectory_config = TrajectoryConfig {
+            max_speed: config.max_speed,
+            ..Default::default()
+        };
+        let trajectory = mission
+            .as_ref()
+            .map(|mission| build_trajectory(&mission.nodes, &trajectory_config))
+            .unwrap_or_default();
+
+        Brain {
+            track,
+            track_height: track_height as f64,
+            mission,
+            trajectory,
+            trajectory_config,
+            costs: costs.clone(),
+            blockages: Blockages::default(),
+            controller: PurePursuitController::new(WHEELBASE),
+            lane_weight: config.lane_weight,
+            behaviour: BehaviourMachine::new(track, config.behaviour.clone()),
+            route_index: 0,
+            position: None,
+            heading_origin: None,
+            last_position_update: None,
+            measured_speed: None,
+            traffic_lights: None,
+            moving_obstacles: ObstacleTracker::new(config.tracker.clone()),
+            signs: Signs::default(),
+            vision: Vision::default(),
+            parked_cars: Vec::new(),
+            reported_obstacles: Vec::new(),
+            known_obstacles: HashSet::new(),
+            last_command: None,
+        }
+    }
+
+    pub fn get_position(&self) -> Option<&CarPosition> {
+        self.position.as_ref()
+    }
+
+    pub fn get_mission(&self) -> Option<&Mission<'static>> {
+        self.mission.as_ref()
+    }
+
+    /// Makes the next decision send every command again, e.g. after the car was stopped by something else
+    pub fn forget_last_command(&mut self) {
+        self.last_command = None;
+    }
+
+    pub fn get_behaviour(&self) -> Behaviour {
+        self.behaviour.get_behaviour()
+    }
+
+    /// Speed measured by the encoder of the car, in meters per second
+    pub fn handle_encoder_speed(&mut self, speed: f32, now: Instant) {
+        self.measured_speed = Some((now, speed as f64));
+    }
+
+    pub fn handle_server_data(&mut self, data: ServerData, now: Instant) {
+        match data {
+            ServerData::CarPos(position) => self.update_position(position, now),
+            ServerData::TrafficLights(status) => {
+                if self.traffic_lights != Some(status) {
+                    debug!("{status}");
+                }
+                self.traffic_lights = Some(status);
+            }
+            ServerData::MovingObstacle(obstacle) => {
+                let (x, y) = obstacle.get_position();
+                let position = Point::new(x as f64, self.track_height - y as f64);
+                let id = obstacle.get_id();
+                self.moving_obstacles.update(id, position, now);
+                if let Some(track) = self.moving_obstacles.get_track(id) {
+                    debug!(
+                        "Moving obstacle {id} drives at {:.2} m/s, heading {:?}",
+                        track.get_speed(),
+                        track.get_heading()
+                    );
+                }
+            }
+        }
+    }
+
+    /// The server's y axis points down, while the track's points up
+    fn update_position(&mut self, position: ServerCarPos, now: Instant) {
+        let x = position.x as f64;
+        let y = self.track_height - position.y as f64;
+
+        // The server only sends positions, so the heading comes from the movement of the car
+        let angle = match (self.heading_origin, &self.position) {
+            (Some((origin_x, origin_y)), Some(last)) => {
+                if (x - origin_x).hypot(y - origin_y) >= MIN_HEADING_DISTANCE {
+                    self.heading_origin = Some((x, y));
+                    (y - origin_y).atan2(x - origin_x)
+                } else {
+                    last.angle
+                }
+            }
+            _ => {
+                self.heading_origin = Some((x, y));
+                self.get_trajectory_heading(x, y)
+            }
+        };
+
+        self.position = Some(CarPosition::new(x, y, angle));
+        self.last_position_update = Some(now);
+    }
+
+    /// Heading of the trajectory closest to the given position, used before the car has moved
+    fn get_trajectory_heading(&self, x: f64, y: f64) -> f64 {
+        self.trajectory
+            .find_closest_index(Point::new(x, y))
+            .map_or(0.0, |index| self.trajectory.points[index].heading)
+    }
+
+    /// Data of a frame of the camera, received at `now`
+    pub fn handle_camera_data(&mut self, frame: CameraFrame, now: Instant) {
+        let lane_width = self.behaviour.get_config().overtaking.lane_width;
+        self.vision.update(&frame, lane_width, now);
+        if let Some(position) = self.position.clone() {
+            self.vision
+                .add_parked_cars(now, &position, lane_width, &mut self.parked_cars);
+            if let Some(roadblock) = self.vision.get_roadblock_ahead(now, &position, lane_width) {
+                self.handle_blocking_obstacle(ObstacleId::Roadblock, roadblock, position.angle);
+            }
+        }
+
+        match frame.data {
+            CameraData::Signs(signs) => {
+                let detected = [
+                    (ObstacleId::TsStop, signs.stop),
+                    (ObstacleId::TsCrosswalk, signs.crosswalk),
+                    (ObstacleId::TsParking, signs.parking_start),
+                    (ObstacleId::TsPriority, signs.priority),
+                ];
+                for (id, confidence) in detected {
+                    if confidence >= SIGN_CONFIDENCE {
+                        self.report_sign(id);
+                    }
+                }
+                self.signs = signs;
+            }
+            CameraData::LanesAngle(_) | CameraData::LaneGeometry(_) | CameraData::Objects(_) => {}
+        }
+    }
+
+    fn report_sign(&mut self, id: ObstacleId) {
+        if let Some(position) = &self.position {
+            self.report_obstacle(id, Point::from(position));
+        }
+    }
+
+    /// Reports an obstacle found at `point`, in the track's frame, unless it is already known
+    fn report_obstacle(&mut self, id: ObstacleId, point: Point) {
+        // The track is in centimeters
+        let Some(node) = self
+            .track
+            .find_closest_node(point.x as f32 * 100.0, point.y as f32 * 100.0)
+        else {
+            return;
+        };
+
+        if self.known_obstacles.insert((id as u8, node.id)) {
+            info!("Detected {id:?} near node {}", node.id);
+            self.reported_obstacles.push(EnvironmentalObstacle {
+                id,
+                x: point.x as f32,
+                y: (self.track_height - point.y) as f32,
+            });
+        }
+    }
+
+    /**
+     * Blocks the lane under an obstacle found at `point`, in the track's frame, by the car driving
+     * with `heading`, and plans the rest of the mission around it if it was in the way
+     */
+    fn handle_blocking_obstacle(&mut self, id: ObstacleId, point: Point, heading: f64) {
+        let blocked = self.blockages.block_obstacle(
+            self.track,
+            id,
+            point.x as f32 * 100.0,
+            point.y as f32 * 100.0,
+            heading as f32,
+        );
+        if blocked.is_some() {
+            self.report_obstacle(id, point);
+            self.replan();
+        }
+    }
+
+    /// Plans the rest of the mission again if it goes through a blocked edge or node
+    fn replan(&mut self) {
+        self.blockages.remove_expired();
+        let Some(mission) = &self.mission else {
+            return;
+        };
+        if !self
+            .blockages
+            .is_path_blocked(&mission.nodes[self.route_index..])
+        {
+            return;
+        }
+
+        match replan_mission(
+            self.track,
+            mission,
+            self.route_index,
+            &self.blockages,
+            &self.costs,
+        ) {
+            Ok(mission) => {
+                info!(
+                    "Planned the mission around the blocked road, {} nodes left",
+                    mission.nodes.len() - self.route_index - 1
+                );
+                self.trajectory = build_trajectory(&mission.nodes, &self.trajectory_config);
+                // It remembers where the car was on the previous trajectory
+                self.controller = PurePursuitController::new(WHEELBASE);
+                self.mission = Some(mission);
+            }
+            Err(e) => {
+                // Otherwise every decision would try again, the car waits or overtakes instead
+                warn!("Failed to plan the mission around the blocked road, keeping it: {e}");
+                self.blockages
+                    .unblock_path(&mission.nodes[self.route_index..]);
+            }
+        }
+    }
+
+    /// Obstacles detected since the last call, in the server's frame
+    pub fn take_reported_obstacles(&mut self) -> Vec<EnvironmentalObstacle> {
+        std::mem::take(&mut self.reported_obstacles)
+    }
+
+    fn has_fresh_position(&self, now: Instant) -> bool {
+        self.last_position_update
+            .is_some_and(|update| now.duration_since(update) < POSITION_TIMEOUT)
+    }
+
+    /**
+     * Moves the route index to the start of the closest of the next few edges of the route,
+     * so that the car never goes back on parts of the route it already drove.
+     */
+    fn update_route_index(&mut self, position: &CarPosition) {
+        let Some(mission) = &self.mission else {
+            return;
+        };
+        let car = Point::new(position.x, position.y);
+        let last = (self.route_index + ROUTE_LOOKAHEAD).min(mission.nodes.len().saturating_sub(1));
+
+        let closest = (self.route_index..last)
+            .map(|index| {
+                let start = Point::from(mission.nodes[index]);
+                let end = Point::from(mission.nodes[index + 1]);
+                let (projection, _) = project_on_segment(car, start, end);
+                (index, car.distance_to(projection))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((index, _)) = closest {
+            self.route_index = index;
+        }
+    }
+
+    /// The next traffic light of the mission, once the car knows the colors of the lights
+    pub fn get_traffic_light_ahead(&self) -> Option<TrafficLightAhead> {
+        find_traffic_light_ahead(
+            self.track,
+            &self.mission.as_ref()?.nodes,
+            self.route_index,
+            self.traffic_lights.as_ref()?,
+        )
+    }
+
+    fn decide(&mut self, now: Instant) -> Command {
+        let position = match &self.position {
+            Some(position) if self.has_fresh_position(now) => position.clone(),
+            _ => return self.behaviour.stop("position unknown"),
+        };
+        if self.mission.is_none() {
+            return self.behaviour.stop("no mission");
+        }
+
+        let speed = match (self.measured_speed, self.last_command) {
+            (Some((update, speed)), _) if now.duration_since(update) < ENCODER_TIMEOUT => speed,
+            (_, Some(Command::Drive { speed, .. })) => speed as f64,
+            _ => 0.0,
+        };
+        let Some(mut path) = self.controller.compute(&self.trajectory, &position, speed) else {
+            return self.behaviour.stop("empty trajectory");
+        };
+        self.follow_lane(now, &position, speed, &mut path);
+        self.update_route_index(&position);
+
+        // Assuming the car drives at the speed planned for the trajectory
+        self.moving_obstacles.remove_stale(now);
+        let collision = self.moving_obstacles.predict_collision(
+            &self.trajectory,
+            self.trajectory.points[path.closest_index].distance,
+            path.speed,
+        );
+
+        let Some(mission) = &self.mission else {
+            return self.behaviour.stop("no mission");
+        };
+        let lane_width = self.behaviour.get_config().overtaking.lane_width;
+        let perception = Perception {
+            now,
+            position: &position,
+            speed,
+            path,
+            finished: path.closest_index + 1 >= self.trajectory.len(),
+            route: &mission.nodes,
+            route_index: self.route_index,
+            signs: &self.signs,
+            traffic_light: self.get_traffic_light_ahead(),
+            obstacle_ahead: self.vision.get_obstacle_ahead(now, speed, lane_width),
+            oncoming_traffic: self.vision.has_oncoming_traffic(now, lane_width),
+            pedestrian_ahead: self.vision.is_pedestrian_ahead(now, lane_width),
+            collision,
+            parked_cars: &self.parked_cars,
+        };
+
+        let command = self.behaviour.update(&perception);
+        if let Some(obstacle) = self.behaviour.take_blocked_obstacle() {
+            self.handle_blocking_obstacle(ObstacleId::StaticCarOnRoad, obstacle, position.angle);
+        }
+        command
+    }
+
+    /**
+     * Steers partly towards the lane seen by the camera, which is more precise than the position
+     * given by the server. Only when following the road, as the lane is lost at intersections.
+     */
+    fn follow_lane(
+        &self,
+        now: Instant,
+        position: &CarPosition,
+        speed: f64,
+        path: &mut PathCommand,
+    ) {
+        if !matches!(
+            self.behaviour.get_behaviour(),
+            Behaviour::LaneFollowing | Behaviour::Highway
+        ) {
+            return;
+        }
+
+        let lookahead = self.controller.get_lookahead(speed);
+        if let Some(target) = self.vision.get_lane_target(now, position, lookahead) {
+            let steering = get_steering_to(position, target, WHEELBASE).clamp(
+                -self.controller.max_steering_angle,
+                self.controller.max_steering_angle,
+            );
+            path.steering_angle += self.lane_weight * (steering - path.steering_angle);
+        }
+    }
+
+    /**
+     * Takes a decision with the data received so far, returning the messages to send to the car.
+     * The car stops when it does not know where it is or has finished its mission.
+     */
+    pub fn tick(&mut self, now: Instant) -> Vec<Message> {
+        let command = self.decide(now);
+        self.get_command_messages(command)
+    }
+
+    /// Only the parts of the command that changed since they were last sent are sent again
+    fn get_command_messages(&mut self, command: Command) -> Vec<Message> {
+        let messages = match (self.last_command, command) {
+            (
+                Some(Command::Drive {
+                    speed: last_speed,
+                    steering: last_steering,
+                }),
+                Command::Drive { speed, steering },
+            ) => {
+                let mut messages = Vec::new();
+                let mut sent = (last_speed, last_steering);
+
+                if (steering - last_steering).abs() >= STEERING_TOLERANCE {
+                    messages.push(Message::Steer(steering));
+                    sent.1 = steering;
+                }
+                if (speed - last_speed).abs() >= SPEED_TOLERANCE {
+                    messages.push(Message::Speed(speed));
+                    sent.0 = speed;
+                }
+
+                self.last_command = Some(Command::Drive {
+                    speed: sent.0,
+                    steering: sent.1,
+                });
+                return messages;
+            }
+            (Some(Command::Brake { .. }), Command::Brake { .. }) => Vec::new(),
+            _ => command.get_messages(),
+        };
+
+        self.last_command = Some(command);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::camera::{BoundingBox, DetectedObject, LaneGeometry, ObjectClass};
+    use crate::track::{get_test_track, plan_mission, Waypoint};
+
+    use super::*;
+
+    /// Node of the mission of [get_test_brain] at which the car sees a roadblock ahead
+    const ROADBLOCK_INDEX: usize = 4;
+    /// Same, but the mission has no way around the roadblock
+    const DEAD_END_INDEX: usize = 5;
+
+    fn get_test_brain() -> Brain {
+        let track = get_test_track();
+        let waypoints = [Waypoint::Node(24), Waypoint::Node(90)];
+        let mission = plan_mission(track, &waypoints, &PathCosts::default()).unwrap();
+
+        Brain::new(
+            track,
+            6.0,
+            Some(mission),
+            &PathCosts::default(),
+            &BrainConfig::default(),
+        )
+    }
+
+    #[test]
+    fn test_stops_without_position() {
+        let mut brain = get_test_brain();
+        let now = Instant::now();
+
+        assert_eq!(brain.tick(now), vec![Message::Brake(0.0)]);
+        assert_eq!(brain.get_behaviour(), Behaviour::Stopped);
+        // Nothing changed, so nothing is sent again
+        assert!(brain.tick(now).is_empty());
+    }
+
+    #[test]
+    fn test_follows_mission() {
+        let mut brain = get_test_brain();
+        let start = brain.get_mission().unwrap().nodes[0];
+        let now = Instant::now();
+
+        let position = ServerCarPos {
+            x: start.get_x() / 100.0,
+            y: 6.0 - start.get_y() / 100.0,
+        };
+        brain.handle_server_data(ServerData::CarPos(position), now);
+
+        let messages = brain.tick(now);
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, Message::Speed(speed) if *speed > 0.0)));
+
+        // The position becomes too old to be trusted
+        let messages = brain.tick(now + POSITION_TIMEOUT);
+        assert_eq!(messages, vec![Message::Brake(0.0)]);
+    }
+
+    #[test]
+    fn test_follows_lane() {
+        let mut brain = get_test_brain();
+        let start = brain.get_mission().unwrap().nodes[0];
+        let now = Instant::now();
+        let position = ServerCarPos {
+            x: start.get_x() / 100.0,
+            y: 6.0 - start.get_y() / 100.0,
+        };
+        brain.handle_server_data(ServerData::CarPos(position), now);
+        brain.tick(now);
+        assert_eq!(brain.get_behaviour(), Behaviour::LaneFollowing);
+
+        let get_steering = |brain: &mut Brain| match brain.decide(now) {
+            Command::Drive { steering, .. } => steering,
+            command => panic!("Unexpected command {command:?}"),
+        };
+        let planned = get_steering(&mut brain);
+
+        // The camera sees the centre of the lane on the left of the car
+        let lane = LaneGeometry {
+            coefficients: [0.2, 0.0, 0.0],
+            offset: -0.2,
+            confidence: 0.9,
+        };
+        let frame = CameraFrame {
+            timestamp: 0,
+            data: CameraData::LaneGeometry(lane),
+        };
+        brain.handle_camera_data(frame, now);
+        assert!(get_steering(&mut brain) > planned + 1.0);
+    }
+
+    /// Drives along the mission of [get_test_brain] up to the node at `index`, where the car sees a roadblock ahead
+    fn see_roadblock(brain: &mut Brain, index: usize) {
+        let now = Instant::now();
+        let nodes = brain.get_mission().unwrap().nodes.clone();
+        for node in &nodes[..=index] {
+            let position = ServerCarPos {
+                x: node.get_x() / 100.0,
+                y: 6.0 - node.get_y() / 100.0,
+            };
+            brain.handle_server_data(ServerData::CarPos(position), now);
+            brain.tick(now);
+        }
+
+        let roadblock = DetectedObject {
+            class: ObjectClass::Roadblock,
+            confidence: 0.9,
+            bounding_box: BoundingBox::default(),
+            distance: 0.2,
+            lateral: 0.0,
+        };
+        let frame = CameraFrame {
+            timestamp: 0,
+            data: CameraData::Objects(vec![roadblock]),
+        };
+        brain.handle_camera_data(frame, now);
+    }
+
+    #[test]
+    fn test_replans_around_roadblock() {
+        let mut brain = get_test_brain();
+        let mission = brain.get_mission().unwrap().clone();
+
+        // After an intersection, so the car can take another road there
+        see_roadblock(&mut brain, ROADBLOCK_INDEX);
+
+        let route_index = brain.route_index;
+        let new_mission = brain.get_mission().unwrap();
+        assert!(brain
+            .blockages
+            .is_path_blocked(&mission.nodes[route_index..]));
+        assert!(!brain
+            .blockages
+            .is_path_blocked(&new_mission.nodes[route_index..]));
+        assert_eq!(
+            new_mission.nodes[..=route_index],
+            mission.nodes[..=route_index]
+        );
+        assert_eq!(new_mission.nodes.last(), mission.nodes.last());
+
+        let reported = brain.take_reported_obstacles();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].id, ObstacleId::Roadblock);
+    }
+    #[test]
+    fn test_keeps_mission_without_way_around() {
+        let mut brain = get_test_brain();
+        let mission = brain.get_mission().unwrap().clone();
+
+        // On a road without any other way to the destination
+        see_roadblock(&mut brain, DEAD_END_INDEX);
+
+        assert_eq!(brain.get_mission().unwrap().nodes, mission.nodes);
+        assert!(!brain
+            .blockages
+            .is_path_blocked(&mission.nodes[brain.route_index..]));
+        assert_eq!(brain.take_reported_obstacles().len(), 1);
+    }
+}
@@ -0,0 +1,234 @@
+use serde::Deserialize;
+use shared::math::{CarPosition, Point};
+
+use crate::track::{Track, TrackNode};
+
+/// Distances are in meters and speeds in meters per second
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OvertakingConfig {
+    /// Distance between the middles of the two lanes
+    pub lane_width: f64,
+    /// Distance driven along the road while changing lanes
+    pub lane_change_distance: f64,
+    /// Distance driven in the other lane after reaching the obstacle, including its length
+    pub pass_distance: f64,
+    /// Obstacles moving faster than this are followed rather than overtaken
+    pub max_obstacle_speed: f64,
+    pub speed: f64,
+    /// Distance ahead of the car of the point it steers towards
+    pub lookahead: f64,
+    /// How long the car waits behind an obstacle it cannot overtake, in seconds, before planning another route
+    pub max_wait: f64,
+}
+
+impl Default for OvertakingConfig {
+    fn default() -> Self {
+        Self {
+            lane_width: 0.37,
+            lane_change_distance: 0.35,
+            pass_distance: 0.4,
+            max_obstacle_speed: 0.1,
+            speed: 0.3,
+            lookahead: 0.3,
+            max_wait: 10.0,
+        }
+    }
+}
+
+impl OvertakingConfig {
+    /// Distance needed to overtake an obstacle that far, from leaving the lane to being back in it
+    pub fn get_overtaking_distance(&self, obstacle_distance: f64) -> f64 {
+        obstacle_distance + self.pass_distance + 2.0 * self.lane_change_distance
+    }
+}
+
+/**
+ * Offset of the car to the left of its lane, changing smoothly from `from` to `to`
+ * while the car drives `length` meters from `start`.
+ * Progress is measured as the distance driven along the route.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LaneOffset {
+    pub from: f64,
+    pub to: f64,
+    pub start: f64,
+    pub length: f64,
+}
+
+impl LaneOffset {
+    pub fn get(&self, progress: f64) -> f64 {
+        if self.length <= 0.0 {
+            return self.to;
+        }
+        let ratio = ((progress - self.start) / self.length).clamp(0.0, 1.0);
+        // Smoothstep, so that the car starts and ends the lane change parallel to the road
+        let ratio = ratio * ratio * (3.0 - 2.0 * ratio);
+        self.from + (self.to - self.from) * ratio
+    }
+
+    pub fn is_done(&self, progress: f64) -> bool {
+        progress >= self.start + self.length
+    }
+}
+
+/**
+ * Whether lane changes are allowed on every edge of the route the car will drive on
+ * in the next `distance` meters, starting with the one it is on.
+ */
+pub fn is_dotted_ahead(
+    track: &Track,
+    route: &[&TrackNode],
+    route_index: usize,
+    position: &CarPosition,
+    distance: f64,
+) -> bool {
+    let car = Point::from(position);
+    let mut driven = 0.0;
+
+    for (index, pair) in route.windows(2).enumerate().skip(route_index) {
+        let dotted = track
+            .get_edge(pair[0].id, pair[1].id)
+            .is_some_and(|edge| edge.dotted);
+        if !dotted {
+            return false;
+        }
+
+        driven += if index == route_index {
+            car.distance_to(Point::from(pair[1]))
+        } else {
+            pair[0].distance_to(pair[1]) as f64 / 100.0
+        };
+        if driven >= distance {
+            return true;
+        }
+    }
+
+    // The route ends before the manoeuvre does
+    false
+}
+
+/**
+ * Point `ahead` meters along the route after the car's projection on its current edge,
+ * moved `offset` meters to the left of the road.
+ */
+pub fn get_offset_target(
+    route: &[&TrackNode],
+    route_index: usize,
+    position: &CarPosition,
+    ahead: f64,
+    offset: f64,
+) -> Option<Point> {
+    let car = Point::from(position);
+    let mut remaining = ahead;
+    let mut segments = route.windows(2).skip(route_index).peekable();
+    let mut first = true;
+
+    while let Some(pair) = segments.next() {
+        let (start, end) = (Point::from(pair[0]), Point::from(pair[1]));
+        let length = start.distance_to(end);
+        if length < 1e-6 {
+            continue;
+        }
+        let (dx, dy) = ((end.x - start.x) / length, (end.y - start.y) / length);
+
+        let along = if first {
+            ((car.x - start.x) * dx + (car.y - start.y) * dy).clamp(0.0, length)
+        } else {
+            0.0
+        };
+        first = false;
+
+        // The last edge is extended so the target always exists
+        if along + remaining <= length || segments.peek().is_none() {
+            let along = along + remaining;
+            return Some(Point::new(
+                start.x + dx * along - dy * offset,
+                start.y + dy * along + dx * offset,
+            ));
+        }
+        remaining -= length - along;
+    }
+
+    None
+}
+
+/// Pure pursuit steering angle, in radians, to reach `target` from `position`
+pub fn get_steering_to(position: &CarPosition, target: Point, wheelbase: f64) -> f64 {
+    let (dx, dy) = (target.x - position.x, target.y - position.y);
+    let (sin, cos) = position.angle.sin_cos();
+    let local_x = dx * cos + dy * sin;
+    let local_y = dy * cos - dx * sin;
+    let distance = local_x.hypot(local_y);
+
+    if distance < 1e-6 {
+        return 0.0;
+    }
+    let alpha = local_y.atan2(local_x);
+    (2.0 * wheelbase * alpha.sin() / distance).atan()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::get_test_track;
+
+    use super::*;
+
+    /// Straight route with dotted edges from node 32 to node 36
+    fn get_route() -> Vec<&'static TrackNode> {
+        let track = get_test_track();
+        (31..=37)
+            .map(|id| track.get_node_by_id(id).unwrap())
+            .collect()
+    }
+
+    fn at_node(node: &TrackNode) -> CarPosition {
+        CarPosition::new(
+            node.get_x() as f64 / 100.0,
+            node.get_y() as f64 / 100.0,
+            0.0,
+        )
+    }
+
+    #[test]
+    fn test_dotted_ahead() {
+        let track = get_test_track();
+        let route = get_route();
+
+        // The first edge has a solid line
+        assert!(!is_dotted_ahead(track, &route, 0, &at_node(route[0]), 0.5));
+        assert!(is_dotted_ahead(track, &route, 1, &at_node(route[1]), 1.2));
+        // The last edge has a solid line again
+        assert!(!is_dotted_ahead(track, &route, 1, &at_node(route[1]), 1.5));
+    }
+
+    #[test]
+    fn test_offset_target() {
+        let route = get_route();
+        let position = at_node(route[1]);
+
+        let target = get_offset_target(&route, 1, &position, 0.5, 0.37).unwrap();
+        let centre = get_offset_target(&route, 1, &position, 0.5, 0.0).unwrap();
+        assert!((target.distance_to(centre) - 0.37).abs() < 1e-6);
+        assert!((centre.distance_to(Point::from(&position)) - 0.5).abs() < 0.01);
+
+        // The road goes towards +x in the track's frame, so its left is +y
+        assert!(target.y > centre.y);
+        assert!(get_steering_to(&position, target, 0.26) > 0.0);
+    }
+
+    #[test]
+    fn test_lane_offset() {
+        let offset = LaneOffset {
+            from: 0.0,
+            to: 0.4,
+            start: 1.0,
+            length: 0.5,
+        };
+
+        assert_eq!(offset.get(0.0), 0.0);
+        assert!((offset.get(1.25) - 0.2).abs() < 1e-9);
+        assert_eq!(offset.get(2.0), 0.4);
+        assert!(!offset.is_done(1.4) && offset.is_done(1.5));
+    }
+}
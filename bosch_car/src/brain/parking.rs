@@ -0,0 +1,344 @@
+use std::f64::consts::FRAC_PI_2;
+
+use serde::Deserialize;
+use shared::math::{CarPosition, Point};
+
+use crate::brain::behaviour::Command;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParkingKind {
+    /// The car ends up in the direction of the road
+    #[default]
+    Parallel,
+    /// The car reverses into the spot and ends up perpendicular to the road
+    Perpendicular,
+}
+
+/// Layout of the parking zone, on the right of the road. Distances are in meters.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ParkingConfig {
+    /// Park in the first free spot of the next parking zone
+    pub enabled: bool,
+    pub kind: ParkingKind,
+    /// Size of a spot along the road
+    pub slot_length: f64,
+    /// Size of a spot across the road
+    pub slot_depth: f64,
+    /// Distance from the parking sign to the first spot
+    pub slot_offset: f64,
+    pub slot_count: usize,
+    /// Distance from the middle of the lane to the spots
+    pub lane_offset: f64,
+    /// Speed of the manoeuvre, in meters per second
+    pub speed: f64,
+    /// In degrees
+    pub max_steering_angle: f64,
+    pub wheelbase: f64,
+    /// How long the car stays parked, in seconds
+    pub parked_duration: f64,
+}
+
+impl Default for ParkingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: ParkingKind::Parallel,
+            slot_length: 0.8,
+            slot_depth: 0.4,
+            slot_offset: 0.2,
+            slot_count: 2,
+            lane_offset: 0.2,
+            speed: 0.15,
+            max_steering_angle: 25.0,
+            wheelbase: 0.26,
+            parked_duration: 3.0,
+        }
+    }
+}
+
+impl ParkingConfig {
+    /// Radius of the tightest turn the car can do
+    pub fn get_turning_radius(&self) -> f64 {
+        self.wheelbase / self.max_steering_angle.to_radians().tan()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParkingSlot {
+    pub index: usize,
+    pub center: Point,
+    /// Direction of the road along the spot, in radians
+    pub heading: f64,
+    /// Distance from the start of the parking zone to the middle of the spot, along the road
+    pub distance: f64,
+}
+
+impl ParkingSlot {
+    /// Position of the point in the frame of the spot, as (along the road, to the left of the road)
+    fn to_local(self, point: Point) -> (f64, f64) {
+        let (dx, dy) = (point.x - self.center.x, point.y - self.center.y);
+        let (sin, cos) = self.heading.sin_cos();
+        (dx * cos + dy * sin, dy * cos - dx * sin)
+    }
+}
+
+/**
+ * Spots of the parking zone starting at `zone_start`, in order along the road, skipping those
+ * in which a car is parked.
+ */
+pub fn find_free_slots(
+    zone_start: &CarPosition,
+    config: &ParkingConfig,
+    parked_cars: &[Point],
+) -> Vec<ParkingSlot> {
+    let (sin, cos) = zone_start.angle.sin_cos();
+    let lateral = -(config.lane_offset + config.slot_depth / 2.0);
+
+    (0..config.slot_count)
+        .map(|index| {
+            let distance = config.slot_offset + (index as f64 + 0.5) * config.slot_length;
+            ParkingSlot {
+                index,
+                center: Point::new(
+                    zone_start.x + distance * cos - lateral * sin,
+                    zone_start.y + distance * sin + lateral * cos,
+                ),
+                heading: zone_start.angle,
+                distance,
+            }
+        })
+        .filter(|slot| {
+            parked_cars.iter().all(|&car| {
+                let (along, across) = slot.to_local(car);
+                along.abs() > config.slot_length / 2.0 || across.abs() > config.slot_depth / 2.0
+            })
+        })
+        .collect()
+}
+
+/// Part of a manoeuvre driven with a constant command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParkingStep {
+    /// Negative when reversing
+    pub speed: f64,
+    /// In degrees, positive to the left
+    pub steering: f64,
+    pub distance: f64,
+}
+
+/**
+ * Plans how to get into `slot` from `position`, driving along the road next to it:
+ * the first step drives straight to where the manoeuvre starts, then the car reverses into
+ * the spot with the tightest turns it can do.
+ */
+pub fn plan_entry(
+    position: &CarPosition,
+    slot: &ParkingSlot,
+    config: &ParkingConfig,
+) -> Vec<ParkingStep> {
+    let radius = config.get_turning_radius();
+    let (along, across) = slot.to_local(Point::from(position));
+    let speed = config.speed.abs();
+    let max_steering = config.max_steering_angle;
+
+    let straight = |distance: f64| ParkingStep {
+        speed: speed.copysign(distance),
+        steering: 0.0,
+        distance: distance.abs(),
+    };
+    let reverse = |steering: f64, distance: f64| ParkingStep {
+        speed: -speed,
+        steering,
+        distance,
+    };
+
+    match config.kind {
+        ParkingKind::Parallel => {
+            // Two opposite arcs of the same angle move the car sideways by 2R(1 - cos(angle))
+            let angle = (1.0 - across / (2.0 * radius)).clamp(-1.0, 1.0).acos();
+            let start = 2.0 * radius * angle.sin();
+
+            vec![
+                straight(start - along),
+                reverse(-max_steering, radius * angle),
+                reverse(max_steering, radius * angle),
+            ]
+        }
+        ParkingKind::Perpendicular => {
+            // A quarter turn moves the car back and sideways by R, then it reverses straight
+            vec![
+                straight(radius - along),
+                reverse(-max_steering, radius * FRAC_PI_2),
+                ParkingStep {
+                    speed: -speed.copysign(across - radius),
+                    steering: 0.0,
+                    distance: (across - radius).abs(),
+                },
+            ]
+        }
+    }
+}
+
+/**
+ * Plans how to leave the spot after `entry`, by driving it backwards
+ * until the car is back on the road where the manoeuvre started.
+ */
+pub fn plan_exit(entry: &[ParkingStep]) -> Vec<ParkingStep> {
+    // The first step only brings the car to the start of the manoeuvre
+    entry
+        .iter()
+        .skip(1)
+        .rev()
+        .map(|step| ParkingStep {
+            speed: -step.speed,
+            ..*step
+        })
+        .collect()
+}
+
+/// Executes the steps of a manoeuvre, measuring the driven distance with the car's positions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParkingManoeuvre {
+    steps: Vec<ParkingStep>,
+    step_index: usize,
+    /// Distance driven since the start of the current step
+    driven: f64,
+    last_position: Point,
+}
+
+impl ParkingManoeuvre {
+    pub fn new(steps: Vec<ParkingStep>, position: &CarPosition) -> ParkingManoeuvre {
+        ParkingManoeuvre {
+            steps,
+            step_index: 0,
+            driven: 0.0,
+            last_position: Point::from(position),
+        }
+    }
+
+    pub fn get_steps(&self) -> &[ParkingStep] {
+        &self.steps
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.step_index >= self.steps.len()
+    }
+
+    /// Command for the current step, or [None] once every step was driven
+    pub fn update(&mut self, position: &CarPosition) -> Option<Command> {
+        let position = Point::from(position);
+        self.driven += position.distance_to(self.last_position);
+        self.last_position = position;
+
+        while let Some(step) = self.steps.get(self.step_index) {
+            if self.driven < step.distance {
+                return Some(Command::Drive {
+                    speed: step.speed as f32,
+                    steering: step.steering as f32,
+                });
+            }
+
+            self.driven -= step.distance;
+            self.step_index += 1;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::math::AngleWrap;
+
+    use super::*;
+
+    /// Drives the steps with a bicycle model, from the rear axle
+    fn simulate(start: &CarPosition, steps: &[ParkingStep], config: &ParkingConfig) -> CarPosition {
+        let mut position = start.clone();
+        let increment = 0.001;
+
+        for step in steps {
+            let direction = step.speed.signum();
+            let curvature = step.steering.to_radians().tan() / config.wheelbase;
+
+            for _ in 0..(step.distance / increment).round() as usize {
+                position.x += direction * increment * position.angle.cos();
+                position.y += direction * increment * position.angle.sin();
+                position.angle += direction * increment * curvature;
+            }
+        }
+
+        position
+    }
+
+    #[test]
+    fn test_parking_ends_in_slot() {
+        for kind in [ParkingKind::Parallel, ParkingKind::Perpendicular] {
+            let config = ParkingConfig {
+                kind,
+                ..Default::default()
+            };
+            let zone_start = CarPosition::new(1.0, 2.0, 0.3);
+            let slot = find_free_slots(&zone_start, &config, &[])[1];
+            let position = CarPosition::new(
+                zone_start.x + slot.distance * zone_start.angle.cos(),
+                zone_start.y + slot.distance * zone_start.angle.sin(),
+                zone_start.angle,
+            );
+
+            let entry = plan_entry(&position, &slot, &config);
+            let parked = simulate(&position, &entry, &config);
+            let expected_heading = match kind {
+                ParkingKind::Parallel => slot.heading,
+                ParkingKind::Perpendicular => slot.heading + FRAC_PI_2,
+            };
+            assert!(
+                Point::from(&parked).distance_to(slot.center) < 0.02,
+                "{kind:?}"
+            );
+            assert!((parked.angle - expected_heading).angle_wrap().abs() < 0.02);
+
+            let exit = plan_exit(&entry);
+            let left = simulate(&parked, &exit, &config);
+            assert!((left.angle - zone_start.angle).angle_wrap().abs() < 0.02);
+            let (_, across) = slot.to_local(Point::from(&left));
+            assert!((across - (config.lane_offset + config.slot_depth / 2.0)).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_occupied_slots_are_skipped() {
+        let config = ParkingConfig::default();
+        let zone_start = CarPosition::new(0.0, 0.0, 0.0);
+        let slots = find_free_slots(&zone_start, &config, &[]);
+        assert_eq!(slots.len(), config.slot_count);
+
+        let free = find_free_slots(&zone_start, &config, &[slots[0].center]);
+        assert_eq!(free, slots[1..]);
+    }
+
+    #[test]
+    fn test_manoeuvre_follows_steps() {
+        let steps = vec![
+            ParkingStep {
+                speed: 0.1,
+                steering: 0.0,
+                distance: 0.5,
+            },
+            ParkingStep {
+                speed: -0.1,
+                steering: 20.0,
+                distance: 0.2,
+            },
+        ];
+        let mut manoeuvre = ParkingManoeuvre::new(steps, &CarPosition::new(0.0, 0.0, 0.0));
+
+        let first = manoeuvre.update(&CarPosition::new(0.3, 0.0, 0.0));
+        assert!(matches!(first, Some(Command::Drive { speed, .. }) if speed > 0.0));
+        let second = manoeuvre.update(&CarPosition::new(0.6, 0.0, 0.0));
+        assert!(matches!(second, Some(Command::Drive { speed, .. }) if speed < 0.0));
+        assert_eq!(manoeuvre.update(&CarPosition::new(0.4, 0.0, 0.0)), None);
+    }
+}
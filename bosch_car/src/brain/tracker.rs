@@ -0,0 +1,224 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use shared::math::{Point, Trajectory};
+use tracing::debug;
+
+/// Distances are in meters and durations in seconds
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrackerConfig {
+    /// How many positions are kept for each obstacle
+    pub history_size: usize,
+    /// Obstacles not seen for this long are forgotten
+    pub stale_timeout: f64,
+    /// The car and an obstacle closer than this collide
+    pub collision_distance: f64,
+    /// How far in the future collisions are predicted
+    pub prediction_horizon: f64,
+    pub prediction_step: f64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            history_size: 10,
+            stale_timeout: 1.0,
+            collision_distance: 0.35,
+            prediction_horizon: 4.0,
+            prediction_step: 0.1,
+        }
+    }
+}
+
+/// Positions of a moving obstacle, in the track's frame, from the oldest to the latest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObstacleTrack {
+    pub id: i32,
+    history: VecDeque<(Instant, Point)>,
+}
+
+impl ObstacleTrack {
+    pub fn get_position(&self) -> Point {
+        self.history
+            .back()
+            .map_or(Point::new(0.0, 0.0), |&(_, point)| point)
+    }
+
+    pub fn get_last_update(&self) -> Option<Instant> {
+        self.history.back().map(|&(time, _)| time)
+    }
+
+    /// Average velocity over the history, in meters per second, as (vx, vy)
+    pub fn get_velocity(&self) -> (f64, f64) {
+        let (Some(&(start, first)), Some(&(end, last))) =
+            (self.history.front(), self.history.back())
+        else {
+            return (0.0, 0.0);
+        };
+        let elapsed = end.saturating_duration_since(start).as_secs_f64();
+        if elapsed <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        ((last.x - first.x) / elapsed, (last.y - first.y) / elapsed)
+    }
+
+    pub fn get_speed(&self) -> f64 {
+        let (vx, vy) = self.get_velocity();
+        vx.hypot(vy)
+    }
+
+    /// Direction of the movement, in radians, or [None] while the obstacle does not move
+    pub fn get_heading(&self) -> Option<f64> {
+        let (vx, vy) = self.get_velocity();
+        (vx.hypot(vy) > 1e-3).then(|| vy.atan2(vx))
+    }
+
+    /// Where the obstacle will be in `time` seconds if it keeps its velocity
+    pub fn predict(&self, time: f64) -> Point {
+        let position = self.get_position();
+        let (vx, vy) = self.get_velocity();
+        Point::new(position.x + vx * time, position.y + vy * time)
+    }
+}
+
+/// Predicted collision between the car and a moving obstacle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collision {
+    pub id: i32,
+    /// In seconds
+    pub time: f64,
+}
+
+/// Follows the moving obstacles sent by the server, by id
+#[derive(Debug, Default)]
+pub struct ObstacleTracker {
+    config: TrackerConfig,
+    tracks: HashMap<i32, ObstacleTrack>,
+}
+
+impl ObstacleTracker {
+    pub fn new(config: TrackerConfig) -> ObstacleTracker {
+        ObstacleTracker {
+            config,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub fn get_track(&self, id: i32) -> Option<&ObstacleTrack> {
+        self.tracks.get(&id)
+    }
+
+    pub fn get_tracks(&self) -> impl Iterator<Item = &ObstacleTrack> {
+        self.tracks.values()
+    }
+
+    /// Adds a position of the obstacle, in the track's frame
+    pub fn update(&mut self, id: i32, position: Point, now: Instant) {
+        let track = self.tracks.entry(id).or_insert_with(|| {
+            debug!("Tracking moving obstacle {id}");
+            ObstacleTrack {
+                id,
+                history: VecDeque::new(),
+            }
+        });
+
+        track.history.push_back((now, position));
+        while track.history.len() > self.config.history_size.max(2) {
+            track.history.pop_front();
+        }
+    }
+
+    /// Forgets the obstacles that were not seen for a while
+    pub fn remove_stale(&mut self, now: Instant) {
+        let timeout = Duration::from_secs_f64(self.config.stale_timeout);
+        self.tracks.retain(|id, track| {
+            let fresh = track
+                .get_last_update()
+                .is_some_and(|update| now.saturating_duration_since(update) < timeout);
+            if !fresh {
+                debug!("Lost moving obstacle {id}");
+            }
+            fresh
+        });
+    }
+
+    /**
+     * First collision between an obstacle and the car, if the car drives along `trajectory` from
+     * `distance` meters at `speed` meters per second and the obstacles keep their velocity.
+     */
+    pub fn predict_collision(
+        &self,
+        trajectory: &Trajectory,
+        distance: f64,
+        speed: f64,
+    ) -> Option<Collision> {
+        if trajectory.is_empty() || self.config.prediction_step <= 0.0 {
+            return None;
+        }
+        let steps = (self.config.prediction_horizon / self.config.prediction_step).ceil() as usize;
+
+        (0..=steps).find_map(|step| {
+            let time = step as f64 * self.config.prediction_step;
+            let car = trajectory
+                .get_point_at_distance(distance + speed.max(0.0) * time)?
+                .position;
+
+            self.get_tracks()
+                .find(|track| track.predict(time).distance_to(car) < self.config.collision_distance)
+                .map(|track| Collision { id: track.id, time })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::math::TrajectoryConfig;
+
+    use super::*;
+
+    fn get_straight_trajectory() -> Trajectory {
+        let waypoints: Vec<Point> = (0..20).map(|x| Point::new(x as f64 * 0.2, 0.0)).collect();
+        Trajectory::from_waypoints(&waypoints, &TrajectoryConfig::default())
+    }
+
+    #[test]
+    fn test_velocity_and_stale_tracks() {
+        let mut tracker = ObstacleTracker::new(TrackerConfig::default());
+        let now = Instant::now();
+
+        for i in 0..5 {
+            let time = now + Duration::from_millis(100 * i);
+            tracker.update(1, Point::new(0.0, 0.05 * i as f64), time);
+        }
+        tracker.update(2, Point::new(3.0, 3.0), now);
+
+        let track = tracker.get_track(1).unwrap();
+        assert!((track.get_speed() - 0.5).abs() < 1e-6);
+        assert!((track.get_heading().unwrap() - std::f64::consts::FRAC_PI_2).abs() < 1e-6);
+        assert_eq!(tracker.get_track(2).unwrap().get_heading(), None);
+
+        tracker.remove_stale(now + Duration::from_millis(1100));
+        assert!(tracker.get_track(1).is_some());
+        assert!(tracker.get_track(2).is_none());
+    }
+
+    #[test]
+    fn test_predict_collision() {
+        let trajectory = get_straight_trajectory();
+        let mut tracker = ObstacleTracker::new(TrackerConfig::default());
+        let now = Instant::now();
+
+        // Crosses the trajectory at x = 2 in about 2 seconds, when the car gets there too
+        tracker.update(1, Point::new(2.0, -1.0), now);
+        tracker.update(1, Point::new(2.0, -0.95), now + Duration::from_millis(100));
+        let collision = tracker.predict_collision(&trajectory, 1.0, 0.5).unwrap();
+        assert_eq!(collision.id, 1);
+        assert!(1.0 < collision.time && collision.time <= 2.0);
+
+        // Waiting for it to pass
+        assert_eq!(tracker.predict_collision(&trajectory, 0.0, 0.0), None);
+    }
+}
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+
+use crate::brain::Perception;
+use crate::server::data::{TrafficLightColor, TrafficLightsStatus};
+use crate::track::{Track, TrackNode};
+
+/// How the car reacts to yellow lights
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrafficLightConfig {
+    /// Time before the car starts braking, in seconds
+    pub reaction_time: f64,
+    /// Deceleration the car can comfortably brake with, in meters per second squared
+    pub max_deceleration: f64,
+}
+
+impl Default for TrafficLightConfig {
+    fn default() -> Self {
+        Self {
+            reaction_time: 0.3,
+            max_deceleration: 0.5,
+        }
+    }
+}
+
+impl TrafficLightConfig {
+    /// Distance the car drives before stopping, from a speed in meters per second
+    pub fn get_stopping_distance(&self, speed: f64) -> f64 {
+        let speed = speed.abs();
+        speed * self.reaction_time + speed * speed / (2.0 * self.max_deceleration)
+    }
+
+    /**
+     * Whether the car has to stop at a light of that color, `distance` meters ahead.
+     * On yellow, the car only goes on when it is too close to stop before the line.
+     */
+    pub fn must_stop(&self, color: TrafficLightColor, distance: f64, speed: f64) -> bool {
+        match color {
+            TrafficLightColor::Red => true,
+            TrafficLightColor::Yellow => distance >= self.get_stopping_distance(speed),
+            TrafficLightColor::Green => false,
+        }
+    }
+}
+
+/// The next traffic light of the route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficLightAhead {
+    /// Id of the light on the server
+    pub id: u8,
+    /// Index in the route of its stop line
+    pub index: usize,
+    pub color: TrafficLightColor,
+}
+
+/**
+ * Finds the next traffic light of `route` from the stop lines of the track's annotations,
+ * with its color in `status`. The light of the node at `route_index` is included, since
+ * the car stops right on it and it still controls the intersection ahead.
+ */
+pub fn find_traffic_light_ahead(
+    track: &Track,
+    route: &[&TrackNode],
+    route_index: usize,
+    status: &TrafficLightsStatus,
+) -> Option<TrafficLightAhead> {
+    let (index, id) = track.find_next_traffic_light(route, route_index)?;
+
+    Some(TrafficLightAhead {
+        id,
+        index,
+        color: status.get(id)?,
+    })
+}
+
+impl Perception<'_> {
+    /// Whether the car has to stop at the next traffic light of its route, if there is one
+    pub fn must_stop_at_light(&self, config: &TrafficLightConfig) -> bool {
+        self.traffic_light.is_some_and(|light| {
+            config.must_stop(light.color, self.get_distance_to(light.index), self.speed)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{find_path, get_test_track, PathCosts};
+
+    use super::*;
+
+    #[test]
+    fn test_yellow_light() {
+        let config = TrafficLightConfig::default();
+        let speed = 0.3;
+        let stopping_distance = config.get_stopping_distance(speed);
+
+        assert!(config.must_stop(TrafficLightColor::Yellow, stopping_distance + 0.1, speed));
+        assert!(!config.must_stop(TrafficLightColor::Yellow, stopping_distance - 0.01, speed));
+        // Once stopped, the car waits for the green light
+        assert!(config.must_stop(TrafficLightColor::Yellow, 0.05, 0.0));
+        assert!(config.must_stop(TrafficLightColor::Red, 0.0, speed));
+        assert!(!config.must_stop(TrafficLightColor::Green, 1.0, speed));
+    }
+
+    #[test]
+    fn test_traffic_light_ahead() {
+        let track = get_test_track();
+        let route = find_path(
+            track,
+            track.get_node_by_id(56).unwrap(),
+            track.get_node_by_id(3).unwrap(),
+            &PathCosts::default(),
+        )
+        .unwrap()
+        .nodes;
+        let entry = route.iter().position(|node| node.id == 2).unwrap();
+        let status = TrafficLightsStatus(
+            TrafficLightColor::Green,
+            TrafficLightColor::Red,
+            TrafficLightColor::Red,
+            TrafficLightColor::Red,
+        );
+
+        let light = find_traffic_light_ahead(track, &route, 0, &status).unwrap();
+        assert_eq!(
+            light,
+            TrafficLightAhead {
+                id: 1,
+                index: entry,
+                color: TrafficLightColor::Green
+            }
+        );
+        assert_eq!(
+            find_traffic_light_ahead(track, &route, entry, &status),
+            Some(light)
+        );
+        assert_eq!(
+            find_traffic_light_ahead(track, &route, entry + 1, &status),
+            None
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::time::{Duration, Instant};
+
+use shared::math::{CarPosition, Point};
+
+use crate::brain::ObstacleAhead;
+use crate::serial::camera::{CameraData, CameraFrame, DetectedObject, LaneGeometry, ObjectClass};
+
+/// Objects detected with a lower confidence are ignored
+pub const DETECTION_CONFIDENCE: f64 = 0.5;
+/// Detections older than this are not taken into account anymore
+const DETECTION_TIMEOUT: Duration = Duration::from_millis(500);
+/// Pedestrians further than this, in meters, do not matter yet
+const PEDESTRIAN_DISTANCE: f64 = 1.0;
+/// Parked cars closer than this, in meters, to a known one are the same car
+const PARKED_CAR_DISTANCE: f64 = 0.3;
+
+/// Position in the track's frame of a point seen by the car at `position`, `lateral` meters on its left
+fn to_track_frame(position: &CarPosition, distance: f64, lateral: f64) -> Point {
+    let (sin, cos) = position.angle.sin_cos();
+    Point::new(
+        position.x + distance * cos - lateral * sin,
+        position.y + distance * sin + lateral * cos,
+    )
+}
+
+/// Car ahead in the previous frame, to estimate its speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PreviousCar {
+    /// Of the frame, in microseconds
+    timestamp: u64,
+    distance: f64,
+}
+
+/// Latest lane and objects seen by the camera, in the frame of the car
+#[derive(Debug, Default)]
+pub struct Vision {
+    lane: Option<(Instant, LaneGeometry)>,
+    objects: Vec<DetectedObject>,
+    objects_update: Option<Instant>,
+    previous_car: Option<PreviousCar>,
+    /// Speed of the car ahead relative to the car, in meters per second
+    relative_speed: f64,
+}
+
+impl Vision {
+    /// Keeps the data of the frame, received at `now`
+    pub fn update(&mut self, frame: &CameraFrame, lane_width: f64, now: Instant) {
+        match &frame.data {
+            CameraData::LaneGeometry(lane) => self.lane = Some((now, *lane)),
+            CameraData::Objects(objects) => {
+                self.objects = objects
+                    .iter()
+                    .filter(|object| object.confidence >= DETECTION_CONFIDENCE)
+                    .copied()
+                    .collect();
+                self.objects_update = Some(now);
+                self.update_car_ahead(frame.timestamp, lane_width);
+            }
+            CameraData::LanesAngle(_) | CameraData::Signs(_) => {}
+        }
+    }
+
+    /// The relative speed is only estimated from frames of the camera, so that delays on the link do not matter
+    fn update_car_ahead(&mut self, timestamp: u64, lane_width: f64) {
+        let Some(distance) = self.find_car_ahead(lane_width).map(|car| car.distance) else {
+            self.previous_car = None;
+            self.relative_speed = 0.0;
+            return;
+        };
+
+        if let Some(previous) = self.previous_car {
+            let elapsed = timestamp.saturating_sub(previous.timestamp) as f64 / 1e6;
+            if elapsed > 0.0 {
+                self.relative_speed = (distance - previous.distance) / elapsed;
+            }
+        } else {
+            self.relative_speed = 0.0;
+        }
+
+        self.previous_car = Some(PreviousCar {
+            timestamp,
+            distance,
+        });
+    }
+
+    fn find_car_ahead(&self, lane_width: f64) -> Option<&DetectedObject> {
+        self.objects
+            .iter()
+            .filter(|object| {
+                object.class == ObjectClass::Car
+                    && object.distance > 0.0
+                    && object.lateral.abs() < lane_width / 2.0
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    fn get_objects(&self, now: Instant) -> &[DetectedObject] {
+        match self.objects_update {
+            Some(update) if now.saturating_duration_since(update) < DETECTION_TIMEOUT => {
+                &self.objects
+            }
+            _ => &[],
+        }
+    }
+
+    /// Latest lane seen by the camera, unless it is too old or uncertain
+    fn get_lane(&self, now: Instant) -> Option<&LaneGeometry> {
+        self.lane
+            .as_ref()
+            .filter(|(update, lane)| {
+                now.saturating_duration_since(*update) < DETECTION_TIMEOUT
+                    && lane.confidence >= DETECTION_CONFIDENCE
+            })
+            .map(|(_, lane)| lane)
+    }
+
+    /// Centre of the lane seen by the camera, `lookahead` meters ahead of the car, in the track's frame
+    pub fn get_lane_target(
+        &self,
+        now: Instant,
+        position: &CarPosition,
+        lookahead: f64,
+    ) -> Option<Point> {
+        let [c0, c1, c2] = self.get_lane(now)?.coefficients;
+        let lateral = c0 + c1 * lookahead + c2 * lookahead * lookahead;
+        Some(to_track_frame(position, lookahead, lateral))
+    }
+
+    /// Closest car in the lane of the car, which drives at `speed` meters per second
+    pub fn get_obstacle_ahead(
+        &self,
+        now: Instant,
+        speed: f64,
+        lane_width: f64,
+    ) -> Option<ObstacleAhead> {
+        if self.get_objects(now).is_empty() {
+            return None;
+        }
+
+        self.find_car_ahead(lane_width).map(|car| ObstacleAhead {
+            distance: car.distance,
+            speed: (speed + self.relative_speed).max(0.0),
+        })
+    }
+
+    /// Whether a pedestrian is close, on the road
+    pub fn is_pedestrian_ahead(&self, now: Instant, lane_width: f64) -> bool {
+        self.get_objects(now).iter().any(|object| {
+            object.class == ObjectClass::Pedestrian
+                && object.distance < PEDESTRIAN_DISTANCE
+                && object.lateral.abs() < lane_width
+        })
+    }
+
+    /// Whether a car is in the lane on the left
+    pub fn has_oncoming_traffic(&self, now: Instant, lane_width: f64) -> bool {
+        self.get_objects(now).iter().any(|object| {
+            object.class == ObjectClass::Car
+                && (lane_width / 2.0..lane_width * 1.5).contains(&object.lateral)
+        })
+    }
+
+    /// Closest roadblock on the road, in the track's frame
+    pub fn get_roadblock_ahead(
+        &self,
+        now: Instant,
+        position: &CarPosition,
+        lane_width: f64,
+    ) -> Option<Point> {
+        self.get_objects(now)
+            .iter()
+            .filter(|object| {
+                object.class == ObjectClass::Roadblock
+                    && object.distance > 0.0
+                    && object.lateral.abs() < lane_width
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+            .map(|object| to_track_frame(position, object.distance, object.lateral))
+    }
+
+    /**
+     * Adds the cars seen on the right of the road to `parked_cars`, in the track's frame,
+     * unless they are already known.
+     */
+    pub fn add_parked_cars(
+        &self,
+        now: Instant,
+        position: &CarPosition,
+        lane_width: f64,
+        parked_cars: &mut Vec<Point>,
+    ) {
+        for object in self.get_objects(now) {
+            if object.class != ObjectClass::Car || object.lateral > -lane_width / 2.0 {
+                continue;
+            }
+
+            let car = to_track_frame(position, object.distance, object.lateral);
+            if parked_cars
+                .iter()
+                .all(|known| known.distance_to(car) >= PARKED_CAR_DISTANCE)
+            {
+                parked_cars.push(car);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::camera::BoundingBox;
+
+    use super::*;
+
+    const LANE_WIDTH: f64 = 0.37;
+
+    fn get_object(class: ObjectClass, distance: f64, lateral: f64) -> DetectedObject {
+        DetectedObject {
+            class,
+            confidence: 0.9,
+            bounding_box: BoundingBox::default(),
+            distance,
+            lateral,
+        }
+    }
+
+    fn get_frame(timestamp: u64, objects: Vec<DetectedObject>) -> CameraFrame {
+        CameraFrame {
+            timestamp,
+            data: CameraData::Objects(objects),
+        }
+    }
+
+    #[test]
+    fn test_car_ahead() {
+        let mut vision = Vision::default();
+        let now = Instant::now();
+
+        // The car ahead gets 0.1 m closer in 0.5 s, while the car drives at 0.3 m/s
+        vision.update(
+            &get_frame(0, vec![get_object(ObjectClass::Car, 1.0, 0.05)]),
+            LANE_WIDTH,
+            now,
+        );
+        vision.update(
+            &get_frame(
+                500_000,
+                vec![
+                    get_object(ObjectClass::Car, 0.9, 0.05),
+                    get_object(ObjectClass::Car, 0.5, 0.4),
+                    get_object(ObjectClass::Pedestrian, 0.6, -0.2),
+                ],
+            ),
+            LANE_WIDTH,
+            now,
+        );
+
+        let obstacle = vision.get_obstacle_ahead(now, 0.3, LANE_WIDTH).unwrap();
+        assert!((obstacle.distance - 0.9).abs() < 1e-9);
+        assert!((obstacle.speed - 0.1).abs() < 1e-9);
+        assert!(vision.has_oncoming_traffic(now, LANE_WIDTH));
+        assert!(vision.is_pedestrian_ahead(now, LANE_WIDTH));
+
+        // Forgotten after a while
+        let later = now + DETECTION_TIMEOUT;
+        assert_eq!(vision.get_obstacle_ahead(later, 0.3, LANE_WIDTH), None);
+        assert!(!vision.is_pedestrian_ahead(later, LANE_WIDTH));
+    }
+
+    #[test]
+    fn test_parked_cars() {
+        let mut vision = Vision::default();
+        let now = Instant::now();
+        vision.update(
+            &get_frame(0, vec![get_object(ObjectClass::Car, 0.5, -0.4)]),
+            LANE_WIDTH,
+            now,
+        );
+
+        // Heading up, so the right of the car is towards x
+        let position = CarPosition::new(1.0, 1.0, std::f64::consts::FRAC_PI_2);
+        let mut parked_cars = Vec::new();
+        vision.add_parked_cars(now, &position, LANE_WIDTH, &mut parked_cars);
+        vision.add_parked_cars(now, &position, LANE_WIDTH, &mut parked_cars);
+
+        assert_eq!(parked_cars.len(), 1);
+        assert!(parked_cars[0].distance_to(Point::new(1.4, 1.5)) < 1e-9);
+    }
+
+    #[test]
+    fn test_lane_target() {
+        let mut vision = Vision::default();
+        let now = Instant::now();
+        let mut lane = LaneGeometry {
+            coefficients: [0.1, 0.0, 0.5],
+            offset: -0.1,
+            confidence: 0.3,
+        };
+        let frame = |lane| CameraFrame {
+            timestamp: 0,
+            data: CameraData::LaneGeometry(lane),
+        };
+        let position = CarPosition::new(1.0, 1.0, std::f64::consts::FRAC_PI_2);
+
+        vision.update(&frame(lane), LANE_WIDTH, now);
+        assert_eq!(vision.get_lane_target(now, &position, 0.5), None);
+
+        // Heading up, so the left of the car is towards -x
+        lane.confidence = 0.9;
+        vision.update(&frame(lane), LANE_WIDTH, now);
+        let target = vision.get_lane_target(now, &position, 0.5).unwrap();
+        assert!(target.distance_to(Point::new(0.775, 1.5)) < 1e-9);
+
+        let later = now + DETECTION_TIMEOUT;
+        assert_eq!(vision.get_lane_target(later, &position, 0.5), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Runtime configuration of the car.
+//!
+//! Values are layered, from lowest to highest priority: defaults, the JSON file given
+//! through `--config`/`BOSCH_CONFIG`, environment variables and command line arguments.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use serde::Deserialize;
+
+use crate::brain::BrainConfig;
+#[cfg(doc)]
+use crate::serial::open_transport;
+use crate::serial::DEFAULT_NUCLEO_PORT;
+use crate::server::steering_wheel::TeleopConfig;
+use crate::track::{PathCosts, TrackConfig, Waypoint};
+
+/// Every option that can be overridden, as (command line argument, environment variable)
+const OPTIONS: [(&str, &str); 11] = [
+    ("track", "BOSCH_TRACK"),
+    ("track-height", "BOSCH_TRACK_HEIGHT"),
+    ("track-annotations", "BOSCH_TRACK_ANNOTATIONS"),
+    ("export-map", "BOSCH_EXPORT_MAP"),
+    ("decision-rate", "BOSCH_DECISION_RATE"),
+    ("max-speed", "BOSCH_MAX_SPEED"),
+    ("nucleo", "BOSCH_NUCLEO"),
+    ("replay", "BOSCH_REPLAY"),
+    ("replay-rate", "BOSCH_REPLAY_RATE"),
+    ("teleop", "BOSCH_TELEOP"),
+    ("teleop-record", "BOSCH_TELEOP_RECORD"),
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub track: TrackConfig,
+    pub brain: BrainConfig,
+    pub path_costs: PathCosts,
+    /// Waypoints the car has to visit, in order
+    pub mission: Vec<Waypoint>,
+    /// Visits the waypoints after the first one in the cheapest order, instead of the given one
+    pub free_order: bool,
+    /// Where to write the map and planned mission at startup, as `.svg` or `.geojson`
+    pub export_map: Option<String>,
+    /// Link to the nucleo board, the serial port of the car by default (see [open_transport])
+    pub nucleo: Option<String>,
+    /// Recording of the steering wheel server to replay instead of driving autonomously
+    pub replay: Option<String>,
+    /// How much faster than recorded the replay goes, 1 by default
+    pub replay_rate: Option<f64>,
+    /// Drives the car with the steering wheel instead of autonomously
+    pub teleop: Option<TeleopConfig>,
+}
+
+impl Config {
+    pub fn get_nucleo(&self) -> &str {
+        self.nucleo.as_deref().unwrap_or(DEFAULT_NUCLEO_PORT)
+    }
+
+    pub fn get_replay_rate(&self) -> f64 {
+        self.replay_rate.unwrap_or(1.0)
+    }
+
+    /// Loads the configuration from the process arguments and environment
+    pub fn load() -> anyhow::Result<Config> {
+        Self::from_sources(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    fn from_sources<I, E>(args: I, env: E) -> anyhow::Result<Config>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let args = parse_args(args)?;
+
+        let mut config = match args.get("config").cloned().or_else(|| env("BOSCH_CONFIG")) {
+            Some(path) => {
+                let file = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read config file {path}"))?;
+                serde_json::from_str(&file)
+                    .with_context(|| format!("Failed to parse config file {path}"))?
+            }
+            None => Config::default(),
+        };
+
+        for (option, variable) in OPTIONS {
+            if let Some(value) = env(variable) {
+                config
+                    .set(option, &value)
+                    .with_context(|| format!("Invalid value for {variable}"))?;
+            }
+        }
+
+        for (option, value) in &args {
+            if option != "config" {
+                config
+                    .set(option, value)
+                    .with_context(|| format!("Invalid value for --{option}"))?;
+            }
+        }
+
+        ensure!(
+            config.brain.decision_rate > 0.0,
+            "The decision rate has to be positive"
+        );
+        ensure!(
+            config.brain.get_period().as_secs_f64() < config.brain.watchdog_timeout,
+            "The watchdog timeout has to be longer than the decision period"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&config.brain.lane_weight),
+            "The lane weight has to be between 0 and 1"
+        );
+        ensure!(
+            (0.01..=100.0).contains(&config.get_replay_rate()),
+            "The replay rate has to be between 0.01 and 100"
+        );
+        if let Some(teleop) = &config.teleop {
+            ensure!(
+                (1.0..=1000.0).contains(&teleop.command_rate),
+                "The teleop command rate has to be between 1 and 1000 Hz"
+            );
+            ensure!(
+                teleop.dead_man_timeout > 0.0 && teleop.dead_man_timeout <= 5.0,
+                "The teleop dead man timeout has to be positive and at most 5 seconds"
+            );
+            ensure!(
+                0.0 <= teleop.min_speed
+                    && teleop.min_speed <= teleop.max_speed
+                    && teleop.max_speed.is_finite(),
+                "The teleop min speed has to be positive and lower than its max speed"
+            );
+            ensure!(
+                (0.0..1.0).contains(&teleop.deadzone),
+                "The teleop deadzone has to be between 0 and 1, excluded"
+            );
+            ensure!(
+                teleop.max_steering > 0.0
+                    && teleop.max_steering.is_finite()
+                    && teleop.steering_scale.is_finite(),
+                "The teleop max steering has to be positive and the steering scale finite"
+            );
+            ensure!(
+                (0.0..=1.0).contains(&teleop.speed_expo)
+                    && (0.0..=1.0).contains(&teleop.steering_expo),
+                "The teleop expo values have to be between 0 and 1"
+            );
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, option: &str, value: &str) -> anyhow::Result<()> {
+        match option {
+            "track" => self.track.map = value.to_string(),
+            "track-height" => self.track.height = value.parse()?,
+            "track-annotations" => self.track.annotations = Some(value.to_string()),
+            "export-map" => self.export_map = Some(value.to_string()),
+            "decision-rate" => self.brain.decision_rate = value.parse()?,
+            "max-speed" => self.brain.max_speed = value.parse()?,
+            "nucleo" => self.nucleo = Some(value.to_string()),
+            "replay" => self.replay = Some(value.to_string()),
+            "replay-rate" => self.replay_rate = Some(value.parse()?),
+            "teleop" => {
+                self.teleop
+                    .get_or_insert_with(Default::default)
+                    .bind_address = value.to_string()
+            }
+            "teleop-record" => {
+                self.teleop.get_or_insert_with(Default::default).record_path =
+                    Some(value.to_string())
+            }
+            _ => bail!("Unknown option --{option}"),
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses arguments of the form `--name value` or `--name=value`
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<HashMap<String, String>> {
+    let mut parsed = HashMap::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("Unexpected argument \"{arg}\""))?;
+
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for --{name}"))?;
+                (name.to_string(), value)
+            }
+        };
+
+        parsed.insert(name, value);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Loads a config file with these teleop settings, written to a temporary file
+    fn load_teleop(teleop: &str) -> anyhow::Result<Config> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, r#"{{"teleop": {teleop}}}"#).unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        Config::from_sources(args(&["--config", &path]), |_| None)
+    }
+
+    #[test]
+    fn test_arguments_override_environment() {
+        let env = |name: &str| match name {
+            "BOSCH_TRACK" => Some("test_track".to_string()),
+            "BOSCH_TRACK_HEIGHT" => Some("15.5".to_string()),
+            _ => None,
+        };
+
+        let config =
+            Config::from_sources(args(&["--track=res/tracks/test_track.graphml"]), env).unwrap();
+
+        assert_eq!(config.track.map, "res/tracks/test_track.graphml");
+        assert_eq!(config.track.height, 15.5);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let no_env = |_: &str| None;
+
+        assert!(Config::from_sources(args(&["--track"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--track-height", "tall"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--unknown", "1"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--decision-rate", "0"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--replay-rate", "-1"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--replay-rate", "1e-300"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--replay-rate", "inf"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--replay-rate", "NaN"]), no_env).is_err());
+        assert!(Config::from_sources(args(&["--replay-rate", "0.5"]), no_env).is_ok());
+
+        assert!(load_teleop(r#"{"dead_man_timeout": 1}"#).is_ok());
+        assert!(load_teleop(r#"{"dead_man_timeout": -1}"#).is_err());
+        assert!(load_teleop(r#"{"dead_man_timeout": 1e300}"#).is_err());
+        assert!(load_teleop(r#"{"command_rate": 0}"#).is_err());
+        assert!(load_teleop(r#"{"command_rate": 1e-300}"#).is_err());
+        assert!(load_teleop(r#"{"command_rate": 1e300}"#).is_err());
+        assert!(load_teleop(r#"{"min_speed": 0.5, "max_speed": 0.2}"#).is_err());
+        assert!(load_teleop(r#"{"min_speed": -0.5}"#).is_err());
+        assert!(load_teleop(r#"{"deadzone": 1}"#).is_err());
+        assert!(load_teleop(r#"{"deadzone": -0.1}"#).is_err());
+        assert!(load_teleop(r#"{"max_steering": 0}"#).is_err());
+        assert!(load_teleop(r#"{"speed_expo": 2}"#).is_err());
+        assert!(load_teleop(r#"{"steering_expo": -1}"#).is_err());
+    }
+}
@@ -1,17 +1,296 @@
-#![allow(dead_code)]
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
+use crate::brain::Brain;
+use crate::config::Config;
+use crate::serial::{Message, NucleoEvent, SerialWriter};
+use crate::server::replay::{ReplayControl, Replayer};
+use crate::server::steering_wheel::{run_steering_wheel_server, TeleopConfig};
+use crate::server::ServerStatus;
+use crate::track::{MapOverlay, Mission, Track, Waypoint};
+
+mod brain;
+mod config;
 mod serial;
 mod server;
 mod track;
 
-fn main() {
+/// How often the status of the competition server clients is logged
+const SERVER_STATUS_PERIOD: Duration = Duration::from_secs(10);
+/// Clients without a message for this long are reported
+const SERVER_STALE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     std::env::set_var("RUST_LOG", "info");
     tracing_subscriber::registry()
         .with(tracing_subscriber::fmt::layer().compact())
         .with(EnvFilter::from_default_env())
         .init();
+
+    let config = Config::load()?;
+    info!("Using {:?}", config);
+
+    if let Some(path) = &config.replay {
+        return replay(path, &config).await;
+    }
+    if let Some(teleop) = &config.teleop {
+        return teleoperate(teleop, &config).await;
+    }
+
+    let track = track::init_track(&config.track)?;
+
+    let mission = if config.mission.is_empty() {
+        None
+    } else {
+        let mission = plan_mission(track, &config)?;
+        info!(
+            "Planned a mission through {} nodes with a cost of {:.0}",
+            mission.nodes.len(),
+            mission.cost
+        );
+        Some(mission)
+    };
+
+    let mut brain = Brain::new(
+        track,
+        config.track.height,
+        mission,
+        &config.path_costs,
+        &config.brain,
+    );
+
+    if let Some(path) = &config.export_map {
+        let overlay = MapOverlay {
+            route: brain
+                .get_mission()
+                .map(|mission| mission.nodes.clone())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        track::export_map(track, &overlay, Path::new(path))?;
+        info!("Exported the map to {path}");
+    }
+    run(&mut brain, &config).await
+}
+
+/// Plans the mission through the waypoints of the config, which must not be empty
+fn plan_mission(track: &'static Track, config: &Config) -> anyhow::Result<Mission<'static>> {
+    if !config.free_order {
+        return track::plan_mission(track, &config.mission, &config.path_costs)
+            .map_err(anyhow::Error::msg);
+    }
+
+    let (order, mission) = track::plan_mission_any_order(
+        track,
+        config.mission[0],
+        &config.mission[1..],
+        None,
+        &config.path_costs,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let waypoints: Vec<Waypoint> = order
+        .iter()
+        .map(|&index| config.mission[index + 1])
+        .collect();
+    info!("Visiting the waypoints in the order {waypoints:?}");
+
+    Ok(mission)
+}
+
+/**
+ * Runs the decision loop until Ctrl-C is pressed, then stops the car
+ */
+async fn run(brain: &mut Brain, config: &Config) -> anyhow::Result<()> {
+    let transport = serial::open_transport(config.get_nucleo())
+        .context("Failed to connect to the nucleo board")?;
+    serial::install_panic_hook(transport.try_clone()?);
+    let nucleo = SerialWriter::start_with_watchdog(transport, config.brain.get_watchdog_timeout())?;
+    nucleo.send(Message::EnableEncoderPublisher(true));
+    let server_status = ServerStatus::default();
+    let mut server_data = server::run_server_listeners(&server_status);
+    let environment = server::environment_server_publisher(&server_status);
+    let camera_data = match serial::camera::get_camera_data_receiver() {
+        Ok(receiver) => Some(receiver),
+        Err(e) => {
+            warn!("Driving without the camera: {e}");
+            None
+        }
+    };
+
+    let mut interval = tokio::time::interval(config.brain.get_period());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut status_interval = tokio::time::interval(SERVER_STATUS_PERIOD);
+    status_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+
+    info!(
+        "Running the decision loop at {} Hz",
+        config.brain.decision_rate
+    );
+
+    loop {
+        tokio::select! {
+            result = &mut shutdown => {
+                if let Err(e) = result {
+                    warn!("Failed to listen for Ctrl-C: {e}");
+                }
+                break;
+            }
+            _ = status_interval.tick() => {
+                log_server_status(&server_status, Instant::now());
+                log_car_status(brain);
+            }
+            _ = interval.tick() => {
+                let now = Instant::now();
+                if nucleo.heartbeat() {
+                    brain.forget_last_command();
+                }
+
+                for event in nucleo.get_events().try_iter() {
+                    if let NucleoEvent::EncoderSpeed(speed) = event {
+                        brain.handle_encoder_speed(speed, now);
+                    }
+                }
+                while let Ok(data) = server_data.try_recv() {
+                    brain.handle_server_data(data, now);
+                }
+                if let Some(camera_data) = &camera_data {
+                    while let Ok(data) = camera_data.try_recv() {
+                        brain.handle_camera_data(data, now);
+                    }
+                }
+
+                for message in brain.tick(now) {
+                    nucleo.send(message);
+                }
+
+                for obstacle in brain.take_reported_obstacles() {
+                    if let Err(TrySendError::Full(obstacle)) = environment.try_send(obstacle) {
+                        warn!("Environment server is too slow, dropped {obstacle}");
+                    }
+                }
+            }
+        }
+    }
+
+    info!("Stopping the car");
+    nucleo.stop();
+
+    Ok(())
+}
+
+/// Warns about the clients of the competition servers that are disconnected or silent
+fn log_server_status(status: &ServerStatus, now: Instant) {
+    for (name, client) in status.get_clients() {
+        let stale = client
+            .get_last_message_age(now)
+            .is_none_or(|age| age > SERVER_STALE_TIMEOUT);
+        if !client.connected || stale {
+            warn!("The {name} server is {client}");
+        } else {
+            info!("The {name} server is {client}");
+        }
+    }
+}
+
+fn log_car_status(brain: &Brain) {
+    match brain.get_position() {
+        Some(position) => info!(
+            "The car is at ({:.2}, {:.2}), {}",
+            position.x,
+            position.y,
+            brain.get_behaviour()
+        ),
+        None => info!(
+            "The car does not know where it is, {}",
+            brain.get_behaviour()
+        ),
+    }
+}
+
+/**
+ * Replays a recording of the steering wheel server until it ends or Ctrl-C is pressed,
+ * pausing or resuming it whenever Enter is pressed
+ */
+async fn replay(path: &str, config: &Config) -> anyhow::Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open recording {path}"))?;
+    let mut transport = serial::open_transport(config.get_nucleo())
+        .context("Failed to connect to the nucleo board")?;
+    serial::install_panic_hook(transport.try_clone()?);
+    let control = ReplayControl::default();
+
+    let rate = config.get_replay_rate();
+    let replay = {
+        let control = control.clone();
+        tokio::task::spawn_blocking(move || {
+            Replayer::new(transport.as_mut(), rate, control).play(BufReader::new(file))
+        })
+    };
+    tokio::pin!(replay);
+
+    {
+        let control = control.clone();
+        thread::spawn(move || {
+            for _ in io::stdin().lines() {
+                if control.is_paused() {
+                    control.resume();
+                    info!("Resumed the replay");
+                } else {
+                    control.pause();
+                    info!("Paused the replay");
+                }
+            }
+        });
+    }
+
+    info!("Replaying {path} at {rate}x, press Enter to pause or resume");
+    tokio::select! {
+        result = &mut replay => return Ok(result??),
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                warn!("Failed to listen for Ctrl-C: {e}");
+            }
+            control.stop();
+        }
+    }
+
+    Ok(replay.await??)
+}
+
+/// Drives the car with the steering wheel until Ctrl-C is pressed, then stops it
+async fn teleoperate(teleop: &TeleopConfig, config: &Config) -> anyhow::Result<()> {
+    let transport = serial::open_transport(config.get_nucleo())
+        .context("Failed to connect to the nucleo board")?;
+    serial::install_panic_hook(transport.try_clone()?);
+    let nucleo = SerialWriter::start(transport)?;
+
+    let result = tokio::select! {
+        result = run_steering_wheel_server(teleop, &nucleo) => {
+            result.context("The steering wheel server failed")
+        }
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                warn!("Failed to listen for Ctrl-C: {e}");
+            }
+            Ok(())
+        }
+    };
+
+    info!("Stopping the car");
+    nucleo.stop();
+    result
 }
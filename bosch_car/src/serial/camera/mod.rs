@@ -0,0 +1,145 @@
+use std::io::{self, Read};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+use tracing::{error, info, warn};
+
+pub use self::protocol::*;
+
+mod protocol;
+
+/// Decoding errors are reported at most this often
+const ERROR_REPORT_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LanesAngle {
+    pub left: f64,
+    pub right: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Signs {
+    pub stop: f64,
+    pub crosswalk: f64,
+    pub parking_start: f64,
+    pub parking_stop: f64,
+    pub priority: f64,
+}
+
+/**
+ * Centre of the lane seen by the camera, in meters, in the frame of the car:
+ * its offset to the left of the car at a distance `x` ahead is
+ * `coefficients[0] + coefficients[1] * x + coefficients[2] * x^2`
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LaneGeometry {
+    pub coefficients: [f64; 3],
+    /// Distance from the car to the centre of the lane, positive when the car is on its left
+    pub offset: f64,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ObjectClass {
+    Pedestrian = 0,
+    Car = 1,
+    TrafficLight = 2,
+    Roadblock = 3,
+}
+
+/// In pixels, from the top left corner of the image
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectedObject {
+    pub class: ObjectClass,
+    pub confidence: f64,
+    pub bounding_box: BoundingBox,
+    /// Distance ahead of the car, in meters
+    pub distance: f64,
+    /// Distance to the left of the car, in meters
+    pub lateral: f64,
+}
+
+/// What the camera sends, each in its own frame (see [FrameDecoder])
+#[derive(Debug, Clone, PartialEq)]
+pub enum CameraData {
+    LanesAngle(LanesAngle),
+    Signs(Signs),
+    LaneGeometry(LaneGeometry),
+    /// Everything detected in an image, possibly nothing
+    Objects(Vec<DetectedObject>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraFrame {
+    /// When the image was taken, in microseconds since the camera started
+    pub timestamp: u64,
+    pub data: CameraData,
+}
+
+type CameraSerialReceiver = Box<dyn SerialPort>;
+
+fn get_camera_serial() -> io::Result<CameraSerialReceiver> {
+    // TODO
+    let serial = mio_serial::new("/dev/ttyACM1", 19200)
+        .timeout(Duration::from_millis(1000))
+        .open()?;
+
+    info!("Camera serial port initialized!");
+
+    Ok(serial)
+}
+
+/**
+ * Reads the camera data on another thread, which stops when the receiver is dropped
+ * or the serial port fails. Corrupted frames are skipped and counted.
+ */
+pub fn get_camera_data_receiver() -> io::Result<Receiver<CameraFrame>> {
+    let mut serial = get_camera_serial()?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut decoder = FrameDecoder::default();
+        let mut buffer = [0_u8; 512];
+        let mut reported_errors = 0;
+        let mut last_report = Instant::now();
+
+        loop {
+            match serial.read(&mut buffer) {
+                Ok(size) => decoder.push(&buffer[..size]),
+                // The camera has nothing to say
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    error!("Camera serial port failed: {e}");
+                    break;
+                }
+            }
+
+            while let Some(frame) = decoder.next_frame() {
+                if sender.send(frame).is_err() {
+                    return;
+                }
+            }
+
+            let stats = decoder.get_stats();
+            if stats.get_error_count() > reported_errors
+                && last_report.elapsed() >= ERROR_REPORT_PERIOD
+            {
+                warn!("Corrupted camera frames: {stats}");
+                reported_errors = stats.get_error_count();
+                last_report = Instant::now();
+            }
+        }
+    });
+
+    Ok(receiver)
+}
@@ -0,0 +1,487 @@
+use std::fmt::{Display, Formatter};
+
+use crate::serial::camera::{
+    BoundingBox, CameraData, CameraFrame, DetectedObject, LaneGeometry, LanesAngle, ObjectClass,
+    Signs,
+};
+
+/// Every frame starts with these bytes
+pub const SYNC: [u8; 2] = [0xAA, 0x55];
+/// The second version added the timestamp to the header, and the lane geometry and objects
+pub const PROTOCOL_VERSION: u8 = 2;
+/// Frames announcing a longer payload are considered corrupted
+pub const MAX_PAYLOAD_SIZE: usize = 1024;
+
+/// Sync, version, kind, payload length and timestamp
+const HEADER_SIZE: usize = SYNC.len() + 12;
+const CRC_SIZE: usize = 2;
+
+const KIND_LANES_ANGLE: u8 = 0;
+const KIND_SIGNS: u8 = 1;
+const KIND_LANE_GEOMETRY: u8 = 2;
+const KIND_OBJECTS: u8 = 3;
+
+/**
+ * CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF), the one computed
+ * by the camera for the whole frame but the sync bytes.
+ */
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+enum PayloadError {
+    UnknownKind,
+    /// The payload does not have the size of the data
+    Size,
+    /// A value is out of its range, e.g. an unknown object class
+    Value,
+}
+
+/// Reads little-endian values from a payload, failing instead of reading out of bounds
+struct PayloadReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl PayloadReader<'_> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], PayloadError> {
+        let (value, rest) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or(PayloadError::Size)?;
+        self.bytes = rest;
+        Ok(*value)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PayloadError> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn read_f32(&mut self) -> Result<f32, PayloadError> {
+        Ok(f32::from_le_bytes(self.read()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, PayloadError> {
+        Ok(f64::from_le_bytes(self.read()?))
+    }
+
+    fn read_object(&mut self) -> Result<DetectedObject, PayloadError> {
+        let class = match self.read_u8()? {
+            0 => ObjectClass::Pedestrian,
+            1 => ObjectClass::Car,
+            2 => ObjectClass::TrafficLight,
+            3 => ObjectClass::Roadblock,
+            _ => return Err(PayloadError::Value),
+        };
+
+        Ok(DetectedObject {
+            class,
+            confidence: self.read_f64()?,
+            bounding_box: BoundingBox {
+                x: self.read_f32()?,
+                y: self.read_f32()?,
+                width: self.read_f32()?,
+                height: self.read_f32()?,
+            },
+            distance: self.read_f64()?,
+            lateral: self.read_f64()?,
+        })
+    }
+}
+
+#[allow(dead_code, reason = "only used by encode_frame")]
+fn encode_payload(data: &CameraData) -> (u8, Vec<u8>) {
+    let mut payload = Vec::new();
+    let write_f64 = |values: &[f64], payload: &mut Vec<u8>| {
+        for value in values {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    };
+
+    let kind = match data {
+        CameraData::LanesAngle(lanes) => {
+            write_f64(&[lanes.left, lanes.right], &mut payload);
+            KIND_LANES_ANGLE
+        }
+        CameraData::Signs(signs) => {
+            write_f64(
+                &[
+                    signs.stop,
+                    signs.crosswalk,
+                    signs.parking_start,
+                    signs.parking_stop,
+                    signs.priority,
+                ],
+                &mut payload,
+            );
+            KIND_SIGNS
+        }
+        CameraData::LaneGeometry(lane) => {
+            write_f64(&lane.coefficients, &mut payload);
+            write_f64(&[lane.offset, lane.confidence], &mut payload);
+            KIND_LANE_GEOMETRY
+        }
+        CameraData::Objects(objects) => {
+            payload.push(objects.len() as u8);
+            for object in objects {
+                payload.push(object.class as u8);
+                write_f64(&[object.confidence], &mut payload);
+                let bounding_box = &object.bounding_box;
+                for value in [
+                    bounding_box.x,
+                    bounding_box.y,
+                    bounding_box.width,
+                    bounding_box.height,
+                ] {
+                    payload.extend_from_slice(&value.to_le_bytes());
+                }
+                write_f64(&[object.distance, object.lateral], &mut payload);
+            }
+            KIND_OBJECTS
+        }
+    };
+
+    (kind, payload)
+}
+
+fn decode_payload(kind: u8, payload: &[u8]) -> Result<CameraData, PayloadError> {
+    let mut reader = PayloadReader { bytes: payload };
+
+    let data = match kind {
+        KIND_LANES_ANGLE => CameraData::LanesAngle(LanesAngle {
+            left: reader.read_f64()?,
+            right: reader.read_f64()?,
+        }),
+        KIND_SIGNS => CameraData::Signs(Signs {
+            stop: reader.read_f64()?,
+            crosswalk: reader.read_f64()?,
+            parking_start: reader.read_f64()?,
+            parking_stop: reader.read_f64()?,
+            priority: reader.read_f64()?,
+        }),
+        KIND_LANE_GEOMETRY => CameraData::LaneGeometry(LaneGeometry {
+            coefficients: [reader.read_f64()?, reader.read_f64()?, reader.read_f64()?],
+            offset: reader.read_f64()?,
+            confidence: reader.read_f64()?,
+        }),
+        KIND_OBJECTS => {
+            let count = reader.read_u8()?;
+            let objects = (0..count)
+                .map(|_| reader.read_object())
+                .collect::<Result<_, _>>()?;
+            CameraData::Objects(objects)
+        }
+        _ => return Err(PayloadError::UnknownKind),
+    };
+
+    if reader.bytes.is_empty() {
+        Ok(data)
+    } else {
+        Err(PayloadError::Size)
+    }
+}
+
+/// Frame sent by the camera for the data
+#[allow(
+    dead_code,
+    reason = "the car only decodes frames, this is for tests and camera simulators"
+)]
+pub fn encode_frame(frame: &CameraFrame) -> Vec<u8> {
+    let (kind, payload) = encode_payload(&frame.data);
+    let mut bytes = SYNC.to_vec();
+    bytes.push(PROTOCOL_VERSION);
+    bytes.push(kind);
+    bytes.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&frame.timestamp.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    let crc = crc16(&bytes[SYNC.len()..]);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+/// What went wrong on the link since it was opened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderStats {
+    pub frames: u64,
+    /// Bytes dropped while looking for the start of a frame
+    pub skipped_bytes: u64,
+    pub version_errors: u64,
+    pub length_errors: u64,
+    pub crc_errors: u64,
+    pub unknown_kinds: u64,
+    /// Valid frames whose payload does not match their kind, or has values out of range
+    pub payload_errors: u64,
+}
+
+impl DecoderStats {
+    pub fn get_error_count(&self) -> u64 {
+        self.version_errors
+            + self.length_errors
+            + self.crc_errors
+            + self.unknown_kinds
+            + self.payload_errors
+    }
+}
+
+impl Display for DecoderStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} frames, {} skipped bytes, errors: {} version, {} length, {} CRC, {} unknown kind, {} payload",
+            self.frames,
+            self.skipped_bytes,
+            self.version_errors,
+            self.length_errors,
+            self.crc_errors,
+            self.unknown_kinds,
+            self.payload_errors
+        )
+    }
+}
+
+/**
+ * Splits the bytes received from the camera into frames. After a corrupted frame, the decoder
+ * looks for the next sync bytes right after the start of the bad one, so it never loses a valid frame
+ * that was hidden in the garbage.
+ */
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    stats: DecoderStats,
+}
+
+impl FrameDecoder {
+    pub fn get_stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Drops the bytes before the next sync bytes
+    fn skip_to_sync(&mut self) {
+        let start = self
+            .buffer
+            .windows(SYNC.len())
+            .position(|window| window == SYNC)
+            // The last byte may be the start of the sync bytes
+            .unwrap_or(self.buffer.len().saturating_sub(SYNC.len() - 1));
+
+        self.stats.skipped_bytes += start as u64;
+        self.buffer.drain(..start);
+    }
+
+    /// Drops the first sync bytes, to look for another frame after them
+    fn drop_frame_start(&mut self) {
+        self.buffer.drain(..SYNC.len());
+    }
+
+    /// Next decoded frame, or [None] until more bytes are received
+    pub fn next_frame(&mut self) -> Option<CameraFrame> {
+        loop {
+            self.skip_to_sync();
+            if self.buffer.len() < HEADER_SIZE {
+                return None;
+            }
+
+            let version = self.buffer[2];
+            let kind = self.buffer[3];
+            let length = u16::from_le_bytes([self.buffer[4], self.buffer[5]]) as usize;
+            let timestamp = u64::from_le_bytes(self.buffer[6..HEADER_SIZE].try_into().unwrap());
+
+            if version != PROTOCOL_VERSION {
+                self.stats.version_errors += 1;
+                self.drop_frame_start();
+                continue;
+            }
+            if length > MAX_PAYLOAD_SIZE {
+                self.stats.length_errors += 1;
+                self.drop_frame_start();
+                continue;
+            }
+
+            let frame_size = HEADER_SIZE + length + CRC_SIZE;
+            if self.buffer.len() < frame_size {
+                return None;
+            }
+
+            let crc_start = HEADER_SIZE + length;
+            let crc = u16::from_le_bytes([self.buffer[crc_start], self.buffer[crc_start + 1]]);
+            if crc != crc16(&self.buffer[SYNC.len()..crc_start]) {
+                self.stats.crc_errors += 1;
+                self.drop_frame_start();
+                continue;
+            }
+
+            let data = decode_payload(kind, &self.buffer[HEADER_SIZE..crc_start]);
+            self.buffer.drain(..frame_size);
+            match data {
+                Ok(data) => {
+                    self.stats.frames += 1;
+                    return Some(CameraFrame { timestamp, data });
+                }
+                Err(PayloadError::UnknownKind) => self.stats.unknown_kinds += 1,
+                Err(PayloadError::Size | PayloadError::Value) => self.stats.payload_errors += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_frames() -> Vec<CameraFrame> {
+        let data = [
+            CameraData::LanesAngle(LanesAngle {
+                left: 1.5,
+                right: -2.0,
+            }),
+            CameraData::Signs(Signs {
+                stop: 0.9,
+                crosswalk: 0.1,
+                parking_start: 0.0,
+                parking_stop: 0.2,
+                priority: 0.5,
+            }),
+            CameraData::LaneGeometry(LaneGeometry {
+                coefficients: [0.05, -0.1, 0.02],
+                offset: 0.03,
+                confidence: 0.8,
+            }),
+            CameraData::Objects(vec![
+                DetectedObject {
+                    class: ObjectClass::Pedestrian,
+                    confidence: 0.7,
+                    bounding_box: BoundingBox {
+                        x: 120.0,
+                        y: 80.0,
+                        width: 30.0,
+                        height: 90.0,
+                    },
+                    distance: 0.8,
+                    lateral: -0.1,
+                },
+                DetectedObject {
+                    class: ObjectClass::Car,
+                    confidence: 0.95,
+                    bounding_box: BoundingBox {
+                        x: 300.0,
+                        y: 100.0,
+                        width: 80.0,
+                        height: 60.0,
+                    },
+                    distance: 1.2,
+                    lateral: 0.35,
+                },
+            ]),
+            CameraData::Objects(Vec::new()),
+        ];
+
+        data.into_iter()
+            .enumerate()
+            .map(|(i, data)| CameraFrame {
+                timestamp: 1_000_000 + i as u64 * 33_333,
+                data,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_crc() {
+        // Check value of CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut decoder = FrameDecoder::default();
+
+        let frames = get_test_frames();
+
+        for frame in &frames {
+            let bytes = encode_frame(frame);
+            // Received in two parts
+            let (first, second) = bytes.split_at(5);
+            decoder.push(first);
+            assert_eq!(decoder.next_frame(), None);
+            decoder.push(second);
+            assert_eq!(decoder.next_frame().as_ref(), Some(frame));
+        }
+
+        assert_eq!(decoder.get_stats().frames, frames.len() as u64);
+        assert_eq!(decoder.get_stats().get_error_count(), 0);
+    }
+
+    #[test]
+    fn test_resynchronises_after_garbage() {
+        let frames = get_test_frames();
+        let mut decoder = FrameDecoder::default();
+
+        let mut corrupted = encode_frame(&frames[0]);
+        let last = corrupted.len() - 3;
+        corrupted[last] ^= 0xFF;
+        let mut unknown = encode_frame(&frames[0]);
+        unknown[3] = 42;
+        let crc = crc16(&unknown[2..unknown.len() - 2]).to_le_bytes();
+        let length = unknown.len();
+        unknown[length - 2..].copy_from_slice(&crc);
+
+        decoder.push(&[0x00, 0xAA, 0x12, 0x55]);
+        decoder.push(&corrupted);
+        decoder.push(&[0xAA, 0x55, 7, 0, 0, 0]);
+        decoder.push(&unknown);
+        decoder.push(&encode_frame(&frames[1]));
+
+        assert_eq!(decoder.next_frame(), Some(frames[1].clone()));
+        assert_eq!(decoder.next_frame(), None);
+
+        let stats = decoder.get_stats();
+        assert_eq!(stats.frames, 1);
+        assert_eq!(stats.crc_errors, 1);
+        assert_eq!(stats.version_errors, 1);
+        assert_eq!(stats.unknown_kinds, 1);
+        assert!(stats.skipped_bytes >= 4);
+    }
+
+    #[test]
+    fn test_wrong_payload_size() {
+        let mut frame = encode_frame(&get_test_frames()[0]);
+        // A lanes angle frame with a single value
+        frame.drain(HEADER_SIZE + 8..HEADER_SIZE + 16);
+        frame[4] = 8;
+        let length = frame.len();
+        let crc = crc16(&frame[2..length - 2]).to_le_bytes();
+        frame[length - 2..].copy_from_slice(&crc);
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&frame);
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.get_stats().payload_errors, 1);
+    }
+
+    #[test]
+    fn test_unknown_object_class() {
+        let frames = get_test_frames();
+        let mut bytes = encode_frame(&frames[3]);
+        // Class of the first object, after the object count
+        bytes[HEADER_SIZE + 1] = 9;
+        let length = bytes.len();
+        let crc = crc16(&bytes[2..length - 2]).to_le_bytes();
+        bytes[length - 2..].copy_from_slice(&crc);
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&bytes);
+        decoder.push(&encode_frame(&frames[4]));
+        assert_eq!(decoder.next_frame(), Some(frames[4].clone()));
+        assert_eq!(decoder.get_stats().payload_errors, 1);
+    }
+}
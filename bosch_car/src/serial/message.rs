@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Message {
@@ -17,6 +18,25 @@ pub enum Message {
     Raw(String),
 }
 
+impl Message {
+    /// Key the board answers the message with, e.g. `1` for `#1:0.20;;`
+    pub fn get_key(&self) -> Option<u8> {
+        match self {
+            Message::Speed(_) => Some(1),
+            Message::Steer(_) => Some(2),
+            Message::Brake(_) => Some(3),
+            Message::EnablePid(_) => Some(4),
+            Message::EnableEncoderPublisher(_) => Some(5),
+            Message::PidParams { .. } => Some(6),
+            Message::Raw(message) => message
+                .trim()
+                .strip_prefix('#')
+                .and_then(|message| message.split_once(':'))
+                .and_then(|(key, _)| key.parse().ok()),
+        }
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -32,3 +52,101 @@ impl Display for Message {
         }
     }
 }
+
+/// Values of the message, like the `0.20` of `#1:0.20;;`
+fn parse_values<const N: usize>(message: &str, values: &str) -> Result<[f32; N], String> {
+    let values: Vec<f32> = if values.is_empty() {
+        Vec::new()
+    } else {
+        values
+            .split(';')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid value in \"{message}\": {e}"))?
+    };
+
+    values.try_into().map_err(|values: Vec<f32>| {
+        format!("Expected {N} values in \"{message}\", got {}", values.len())
+    })
+}
+
+fn parse_flag(message: &str, values: &str) -> Result<bool, String> {
+    match values {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("Expected 0 or 1 in \"{message}\"")),
+    }
+}
+
+/**
+ * Parses a message as it is sent to the board, e.g. `#1:0.20;;`. Commands of unknown keys and
+ * anything else that is not a command are kept as [Message::Raw], so a raw message only parses
+ * back to itself when it does not look like a known command.
+ */
+impl FromStr for Message {
+    type Err = String;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let message = message.trim();
+        let Some((key, values)) = message
+            .strip_prefix('#')
+            .and_then(|command| command.strip_suffix(";;"))
+            .and_then(|command| command.split_once(':'))
+        else {
+            return Ok(Message::Raw(message.to_string()));
+        };
+
+        Ok(match key {
+            "1" => Message::Speed(parse_values::<1>(message, values)?[0]),
+            "2" => Message::Steer(parse_values::<1>(message, values)?[0]),
+            "3" => Message::Brake(parse_values::<1>(message, values)?[0]),
+            "4" => Message::EnablePid(parse_flag(message, values)?),
+            "5" => Message::EnableEncoderPublisher(parse_flag(message, values)?),
+            "6" => {
+                let [k_p, k_i, k_d, k_f] = parse_values(message, values)?;
+                Message::PidParams { k_p, k_i, k_d, k_f }
+            }
+            _ => Message::Raw(message.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            Message::Speed(0.25),
+            Message::Steer(-12.5),
+            Message::Brake(3.0),
+            Message::EnablePid(true),
+            Message::EnableEncoderPublisher(false),
+            Message::PidParams {
+                k_p: 0.115,
+                k_i: 0.81,
+                k_d: 0.00022,
+                k_f: 0.04,
+            },
+            Message::Raw("#42:1;;".to_string()),
+            Message::Raw("hello".to_string()),
+        ];
+
+        for message in messages {
+            assert_eq!(message.to_string().parse(), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        assert!("#1:fast;;".parse::<Message>().is_err());
+        assert!("#2:1;2;;".parse::<Message>().is_err());
+        assert!("#4:2;;".parse::<Message>().is_err());
+        assert!("#1:;;".parse::<Message>().is_err());
+        assert_eq!(
+            "  #1:0.20;;\r\n".parse::<Message>(),
+            Ok(Message::Speed(0.2))
+        );
+    }
+}
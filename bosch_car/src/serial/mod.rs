@@ -1,90 +1,193 @@
-use std::mem::MaybeUninit;
-#[cfg(test)]
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Once;
-use std::time::Duration;
-#[cfg(test)]
-use tokio::task;
+use std::io;
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use tokio::task::JoinHandle;
-use tracing::info;
-
-use crate::serial::sender::{MessageSender, SerialMessageSender};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use tracing::{debug, error};
 
 pub use self::message::*;
+pub use self::response::*;
+pub use self::simulator::*;
+pub use self::transport::*;
+pub use self::watchdog::*;
 
 pub mod camera;
 mod message;
-mod sender;
+mod response;
+mod simulator;
+mod transport;
+mod watchdog;
 
-fn init_serial() -> SerialMessageSender {
-    let serial = SerialMessageSender(
-        mio_serial::new("/dev/ttyACM0", 19200)
-            .timeout(Duration::from_millis(100))
-            .open()
-            .expect("Failed to open serial port"),
-    );
+/// Events not read yet past this are dropped
+const EVENT_CAPACITY: usize = 256;
 
-    info!("Serial port initialized");
+/**
+ * Sends a message to the nucleo board on the current thread, then waits for its response
+ * to log it.
+ */
+pub fn send_blocking(transport: &mut dyn NucleoTransport, message: &Message) -> io::Result<()> {
+    let string = message.to_string();
+    transport.write_all(string.as_bytes())?;
+    let mut response = [0_u8; 512];
+
+    match transport.read(&mut response) {
+        Ok(size) => debug!(
+            "Response for \"{}\": {:?}",
+            string.trim(),
+            str::from_utf8(&response[..size]).map(str::parse::<Response>)
+        ),
+        Err(e) => debug!("No response for \"{}\": {}", string.trim(), e),
+    }
 
-    serial
+    Ok(())
 }
 
-fn get_serial() -> &'static mut SerialMessageSender {
-    static mut SINGLETON: MaybeUninit<SerialMessageSender> = MaybeUninit::uninit();
-    static ONCE: Once = Once::new();
-
-    unsafe {
-        ONCE.call_once(|| {
-            SINGLETON.write(init_serial());
-        });
-
-        SINGLETON.assume_init_mut()
+fn write_message(
+    transport: &mut dyn NucleoTransport,
+    pending: &PendingCommands,
+    message: &Message,
+) {
+    pending.push(message, Instant::now());
+    if let Err(e) = transport.write_all(message.to_string().as_bytes()) {
+        error!("Failed to send \"{}\": {e}", message.to_string().trim());
     }
 }
 
-#[cfg(test)]
-pub fn get_test_queue() -> &'static mut (Sender<Message>, Receiver<Message>) {
-    static mut SINGLETON: MaybeUninit<(Sender<Message>, Receiver<Message>)> = MaybeUninit::uninit();
-    static ONCE: Once = Once::new();
+/**
+ * Sends messages to the nucleo board from a dedicated thread, in the order they were queued,
+ * so that callers never block on the serial port. Another thread reads the responses of the
+ * board, see [SerialWriter::get_events].
+ */
+pub struct SerialWriter {
+    sender: crossbeam_channel::Sender<Message>,
+    thread: thread::JoinHandle<()>,
+    events: Receiver<NucleoEvent>,
+    reader: thread::JoinHandle<()>,
+    reading: Arc<AtomicBool>,
+    watchdog: Option<Arc<Watchdog>>,
+}
 
-    unsafe {
-        ONCE.call_once(|| {
-            SINGLETON.write(channel::<Message>());
-        });
+impl SerialWriter {
+    pub fn start(transport: Box<dyn NucleoTransport>) -> io::Result<SerialWriter> {
+        SerialWriter::spawn(transport, None)
+    }
 
-        SINGLETON.assume_init_mut()
+    /**
+     * Also stops the car when [SerialWriter::heartbeat] is not called for `timeout`,
+     * see [Watchdog]
+     */
+    pub fn start_with_watchdog(
+        transport: Box<dyn NucleoTransport>,
+        timeout: Duration,
+    ) -> io::Result<SerialWriter> {
+        SerialWriter::spawn(transport, Some(Arc::new(Watchdog::new(timeout))))
     }
-}
 
-/**
- * Send a message to the nucleo board on the current thread.
- */
-#[cfg(not(test))]
-pub fn send_blocking(message: Message) -> std::io::Result<()> {
-    get_serial().send_blocking(message)
-}
+    fn spawn(
+        mut transport: Box<dyn NucleoTransport>,
+        watchdog: Option<Arc<Watchdog>>,
+    ) -> io::Result<SerialWriter> {
+        let pending = PendingCommands::default();
+        let (event_sender, events) = crossbeam_channel::bounded(EVENT_CAPACITY);
+        let reading = Arc::new(AtomicBool::new(true));
+        let reader = {
+            let reader = ResponseReader::new(pending.clone(), event_sender);
+            let transport = transport.try_clone()?;
+            let reading = reading.clone();
+            thread::spawn(move || reader.run(transport, reading))
+        };
+
+        let (sender, receiver) = crossbeam_channel::unbounded::<Message>();
+        let thread = {
+            let watchdog = watchdog.clone();
+            thread::spawn(move || loop {
+                let message = match &watchdog {
+                    Some(watchdog) => receiver.recv_timeout(watchdog.get_wait(Instant::now())),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let message = match message {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if let Some(watchdog) = &watchdog {
+                    if watchdog.check(&receiver, Instant::now()) {
+                        for message in &STOP_MESSAGES {
+                            write_message(transport.as_mut(), &pending, message);
+                        }
+                    }
+                    if message
+                        .as_ref()
+                        .is_some_and(|message| !watchdog.allows(message))
+                    {
+                        continue;
+                    }
+                }
+                if let Some(message) = message {
+                    write_message(transport.as_mut(), &pending, &message);
+                }
+            })
+        };
+
+        Ok(SerialWriter {
+            sender,
+            thread,
+            events,
+            reader,
+            reading,
+            watchdog,
+        })
+    }
 
-/**
- * Send a message to the nucleo board asynchronously on another thread.
- */
-#[cfg(not(test))]
-pub fn send(message: Message) -> JoinHandle<()> {
-    get_serial().send(message)
-}
+    /**
+     * Tells the watchdog that the control loop is alive, returning whether the car was
+     * stopped since the last heartbeat. Always false without a watchdog.
+     */
+    pub fn heartbeat(&self) -> bool {
+        self.watchdog
+            .as_ref()
+            .is_some_and(|watchdog| watchdog.heartbeat())
+    }
 
-#[cfg(test)]
-pub fn send_blocking(message: Message) -> std::io::Result<()> {
-    let (sender, _) = get_test_queue();
-    sender
-        .clone()
-        .send(message)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-}
+    /// Acks, errors and encoder speeds sent by the board, in the order they were received
+    pub fn get_events(&self) -> &Receiver<NucleoEvent> {
+        &self.events
+    }
 
-#[cfg(test)]
-pub fn send(message: Message) -> JoinHandle<()> {
-    task::spawn_blocking(|| send_blocking(message).unwrap())
+    /// Queues the message, unless the watchdog stopped the car and it would make it move again
+    pub fn send(&self, message: Message) {
+        match &self.watchdog {
+            Some(watchdog) => watchdog.send(&self.sender, message),
+            // The thread only stops once the writer is dropped, so this cannot fail
+            None => {
+                let _ = self.sender.send(message);
+            }
+        }
+    }
+
+    /**
+     * Stops the car and waits until every queued message has been sent. The watchdog keeps
+     * running until then.
+     */
+    pub fn stop(self) {
+        for message in STOP_MESSAGES {
+            self.send(message);
+        }
+
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            error!("Serial writer thread panicked, the car might not be stopped");
+        }
+
+        self.reading.store(false, Ordering::Relaxed);
+        if self.reader.join().is_err() {
+            error!("Serial reader thread panicked");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -92,13 +195,45 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_serial_through_queue() {
-        let (_, rx) = get_test_queue();
-
-        let sent_message = Message::Speed(1.0);
-        send_blocking(sent_message.clone()).unwrap();
-
-        let received_message = rx.recv().unwrap();
-        assert_eq!(sent_message, received_message);
+    fn test_writer_drives_simulated_board() {
+        let board = SimulatedBoard::new();
+        let writer = SerialWriter::start(Box::new(board.clone())).unwrap();
+
+        writer.send(Message::Steer(10.0));
+        writer.send(Message::Raw("#42:1;;".to_string()));
+        let events = writer.get_events();
+        let timeout = ACK_TIMEOUT * 2;
+        assert!(matches!(
+            events.recv_timeout(timeout),
+            Ok(NucleoEvent::Acknowledged {
+                message: Message::Steer(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            events.recv_timeout(timeout),
+            Ok(NucleoEvent::Rejected {
+                message: Some(Message::Raw("#42:1;;".to_string())),
+                error: "unknown command".to_string()
+            })
+        );
+
+        writer.send(Message::EnableEncoderPublisher(true));
+        writer.send(Message::Speed(0.3));
+        let speed = events
+            .iter()
+            .find_map(|event| match event {
+                NucleoEvent::EncoderSpeed(speed) => Some(speed),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(speed, 0.3);
+        writer.stop();
+
+        let state = board.get_state();
+        assert_eq!(state.speed, 0.0);
+        assert_eq!(state.steering, 0.0);
+        assert!(state.braking);
+        assert_eq!(state.commands, 5);
     }
 }
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Sender, TrySendError};
+use tracing::{debug, error, warn};
+
+use crate::serial::{Message, NucleoTransport};
+
+/// Commands not acknowledged by the board after this long are reported
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Key of the messages of the encoder publisher
+const ENCODER_KEY: u8 = 5;
+
+/// A line sent by the board, like `@1:ack;;`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// The command with this key was applied
+    Ack(u8),
+    /// The command with this key was rejected
+    Error { key: u8, error: String },
+    /// Measured by the encoder, in meters per second
+    EncoderSpeed(f32),
+}
+
+impl FromStr for Response {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (key, value) = line
+            .trim()
+            .strip_prefix('@')
+            .and_then(|response| response.strip_suffix(";;"))
+            .and_then(|response| response.split_once(':'))
+            .ok_or_else(|| format!("Invalid response \"{}\"", line.trim()))?;
+        let key: u8 = key
+            .parse()
+            .map_err(|_| format!("Invalid key in \"{}\"", line.trim()))?;
+
+        if value == "ack" {
+            return Ok(Response::Ack(key));
+        }
+        match value.parse() {
+            Ok(speed) if key == ENCODER_KEY => Ok(Response::EncoderSpeed(speed)),
+            _ => Ok(Response::Error {
+                key,
+                error: value.to_string(),
+            }),
+        }
+    }
+}
+
+/// What happened on the link to the board
+#[derive(Debug, Clone, PartialEq)]
+pub enum NucleoEvent {
+    Acknowledged {
+        message: Message,
+        /// Between sending the command and receiving its ack
+        latency: Duration,
+    },
+    /// `message` is [None] when the error does not match any sent command
+    Rejected {
+        message: Option<Message>,
+        error: String,
+    },
+    /// Not acknowledged after [ACK_TIMEOUT]
+    AckTimeout(Message),
+    /// In meters per second
+    EncoderSpeed(f32),
+}
+
+/// Command waiting for its ack
+#[derive(Debug, Clone)]
+struct PendingCommand {
+    key: u8,
+    message: Message,
+    sent: Instant,
+}
+
+/// Commands sent to the board, in order, until they are acknowledged
+#[derive(Debug, Clone, Default)]
+pub struct PendingCommands(Arc<Mutex<VecDeque<PendingCommand>>>);
+
+impl PendingCommands {
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<PendingCommand>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Commands without a key are not answered by the board, so they are not waited for
+    pub fn push(&self, message: &Message, now: Instant) {
+        if let Some(key) = message.get_key() {
+            self.lock().push_back(PendingCommand {
+                key,
+                message: message.clone(),
+                sent: now,
+            });
+        }
+    }
+
+    /// The oldest command with the key, since the board answers in order
+    fn take(&self, key: u8) -> Option<PendingCommand> {
+        let mut pending = self.lock();
+        let index = pending.iter().position(|command| command.key == key)?;
+        pending.remove(index)
+    }
+
+    fn take_expired(&self, now: Instant) -> Vec<PendingCommand> {
+        let mut pending = self.lock();
+        let mut expired = Vec::new();
+        while pending
+            .front()
+            .is_some_and(|command| now.saturating_duration_since(command.sent) >= ACK_TIMEOUT)
+        {
+            expired.extend(pending.pop_front());
+        }
+        expired
+    }
+}
+
+/**
+ * Reads what the board sends, turning it into [NucleoEvent]s matched to the commands
+ * of [PendingCommands]. Rejected and unacknowledged commands are also logged.
+ */
+pub struct ResponseReader {
+    pending: PendingCommands,
+    events: Sender<NucleoEvent>,
+    /// Bytes of an incomplete line
+    buffer: Vec<u8>,
+}
+
+impl ResponseReader {
+    pub fn new(pending: PendingCommands, events: Sender<NucleoEvent>) -> ResponseReader {
+        ResponseReader {
+            pending,
+            events,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn send(&self, event: NucleoEvent) {
+        if let Err(TrySendError::Full(event)) = self.events.try_send(event) {
+            debug!("Nobody reads the events of the board, dropped {event:?}");
+        }
+    }
+
+    /// Handles the bytes received at `now`, possibly several or incomplete lines
+    pub fn push(&mut self, bytes: &[u8], now: Instant) {
+        self.buffer.extend_from_slice(bytes);
+
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() {
+                self.handle_line(&line, now);
+            }
+        }
+    }
+
+    fn handle_line(&self, line: &str, now: Instant) {
+        let event = match line.parse() {
+            Ok(Response::Ack(key)) => match self.pending.take(key) {
+                Some(command) => NucleoEvent::Acknowledged {
+                    message: command.message,
+                    latency: now.saturating_duration_since(command.sent),
+                },
+                None => {
+                    debug!("Unexpected ack for command {key}");
+                    return;
+                }
+            },
+            Ok(Response::Error { key, error }) => {
+                let message = self.pending.take(key).map(|command| command.message);
+                match &message {
+                    Some(message) => {
+                        warn!(
+                            "The board rejected \"{}\": {error}",
+                            message.to_string().trim()
+                        )
+                    }
+                    None => warn!("The board sent an error for command {key}: {error}"),
+                }
+                NucleoEvent::Rejected { message, error }
+            }
+            Ok(Response::EncoderSpeed(speed)) => NucleoEvent::EncoderSpeed(speed),
+            Err(e) => {
+                debug!("{e}");
+                return;
+            }
+        };
+
+        self.send(event);
+    }
+
+    /// Reports the commands that were not acknowledged in time
+    pub fn check_timeouts(&self, now: Instant) {
+        for command in self.pending.take_expired(now) {
+            warn!(
+                "The board did not acknowledge \"{}\"",
+                command.message.to_string().trim()
+            );
+            self.send(NucleoEvent::AckTimeout(command.message));
+        }
+    }
+
+    /// Reads from the board until `running` is cleared or the link fails
+    pub fn run(mut self, mut transport: Box<dyn NucleoTransport>, running: Arc<AtomicBool>) {
+        let mut buffer = [0_u8; 512];
+
+        while running.load(Ordering::Relaxed) {
+            match transport.read(&mut buffer) {
+                Ok(size) => self.push(&buffer[..size], Instant::now()),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    error!("Stopped reading from the nucleo board: {e}");
+                    return;
+                }
+            }
+            self.check_timeouts(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!("@1:ack;;\r\n".parse(), Ok(Response::Ack(1)));
+        assert_eq!("@5:0.25;;".parse(), Ok(Response::EncoderSpeed(0.25)));
+        assert_eq!(
+            "@2:out of range;;".parse(),
+            Ok(Response::Error {
+                key: 2,
+                error: "out of range".to_string()
+            })
+        );
+        assert!("#1:0.20;;".parse::<Response>().is_err());
+        assert!("@speed:ack;;".parse::<Response>().is_err());
+    }
+
+    #[test]
+    fn test_matches_acks_to_commands() {
+        let pending = PendingCommands::default();
+        let (sender, events) = crossbeam_channel::unbounded();
+        let mut reader = ResponseReader::new(pending.clone(), sender);
+        let now = Instant::now();
+
+        pending.push(&Message::Speed(0.3), now);
+        pending.push(&Message::Steer(5.0), now);
+        pending.push(&Message::Speed(0.4), now);
+        // Not answered by the board
+        pending.push(&Message::Raw("hello".to_string()), now);
+
+        let later = now + Duration::from_millis(20);
+        reader.push(b"@2:out of range;;\r\n@1:a", later);
+        reader.push(b"ck;;\r\n", later);
+        assert_eq!(
+            events.try_recv(),
+            Ok(NucleoEvent::Rejected {
+                message: Some(Message::Steer(5.0)),
+                error: "out of range".to_string()
+            })
+        );
+        assert_eq!(
+            events.try_recv(),
+            Ok(NucleoEvent::Acknowledged {
+                message: Message::Speed(0.3),
+                latency: Duration::from_millis(20)
+            })
+        );
+
+        reader.check_timeouts(now + ACK_TIMEOUT);
+        assert_eq!(
+            events.try_recv(),
+            Ok(NucleoEvent::AckTimeout(Message::Speed(0.4)))
+        );
+        assert!(events.try_recv().is_err());
+    }
+}
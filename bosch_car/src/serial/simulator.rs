@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use tracing::debug;
+
+use crate::serial::{NucleoTransport, READ_TIMEOUT};
+
+/// In degrees, like the firmware
+const MAX_STEERING_ANGLE: f32 = 25.0;
+/// In meters per second
+const MAX_SPEED: f32 = 1.0;
+
+/// What the firmware of the board was told to do
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BoardState {
+    /// In meters per second
+    pub speed: f32,
+    /// In degrees, positive to the left
+    pub steering: f32,
+    pub braking: bool,
+    pub pid_enabled: bool,
+    pub encoder_publisher: bool,
+    /// As (k_p, k_i, k_d, k_f)
+    pub pid_params: (f32, f32, f32, f32),
+    /// How many commands were acknowledged
+    pub commands: usize,
+}
+
+#[derive(Debug, Default)]
+struct Board {
+    state: BoardState,
+    /// Bytes of an incomplete command
+    input: Vec<u8>,
+    /// Responses not read yet
+    output: VecDeque<u8>,
+}
+
+impl Board {
+    fn execute(&mut self, line: &str) {
+        let Some((key, values)) = line
+            .strip_prefix('#')
+            .and_then(|command| command.strip_suffix(";;"))
+            .and_then(|command| command.split_once(':'))
+        else {
+            debug!("Simulated board ignored {line:?}");
+            return;
+        };
+
+        let response = match self.apply(key, values) {
+            Ok(()) => {
+                self.state.commands += 1;
+                "ack"
+            }
+            Err(error) => error,
+        };
+        self.output
+            .extend(format!("@{key}:{response};;\r\n").as_bytes());
+    }
+
+    /// Applies a command the way the firmware does, or returns the error it answers with
+    fn apply(&mut self, key: &str, values: &str) -> Result<(), &'static str> {
+        let values = values
+            .split(';')
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "syntax error")?;
+        let state = &mut self.state;
+
+        match (key, values.as_slice()) {
+            ("1", &[speed]) if speed.abs() <= MAX_SPEED => {
+                state.speed = speed;
+                state.braking = false;
+            }
+            ("2", &[angle]) | ("3", &[angle]) if angle.abs() > MAX_STEERING_ANGLE => {
+                return Err("out of range");
+            }
+            ("2", &[angle]) => state.steering = angle,
+            ("3", &[angle]) => {
+                state.speed = 0.0;
+                state.steering = angle;
+                state.braking = true;
+            }
+            ("4", &[enable]) if enable == 0.0 || enable == 1.0 => state.pid_enabled = enable == 1.0,
+            ("5", &[enable]) if enable == 0.0 || enable == 1.0 => {
+                state.encoder_publisher = enable == 1.0
+            }
+            ("6", &[k_p, k_i, k_d, k_f]) => state.pid_params = (k_p, k_i, k_d, k_f),
+            ("1", &[_]) | ("4", &[_]) | ("5", &[_]) => return Err("out of range"),
+            ("1" | "2" | "3" | "4" | "5" | "6", _) => return Err("syntax error"),
+            _ => return Err("unknown command"),
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Nucleo board simulated in memory, replying to the commands like the firmware does:
+ * `#1:0.20;;` is answered by `@1:ack;;`, or by `@1:<error>;;` when the command is invalid.
+ * While the encoder publisher is enabled, the speed is published as `@5:0.20;;` whenever
+ * a reader waited [READ_TIMEOUT] without anything else to read.
+ * Clones share the same board.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedBoard {
+    board: Arc<(Mutex<Board>, Condvar)>,
+}
+
+impl SimulatedBoard {
+    pub fn new() -> SimulatedBoard {
+        SimulatedBoard::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Board> {
+        // The board stays consistent even if a thread panicked while holding it
+        self.board.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[allow(
+        dead_code,
+        reason = "for tests and tools checking what the car would do"
+    )]
+    pub fn get_state(&self) -> BoardState {
+        self.lock().state.clone()
+    }
+}
+
+impl Write for SimulatedBoard {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut board = self.lock();
+        board.input.extend_from_slice(bytes);
+
+        while let Some(end) = board.input.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = board.input.drain(..=end).collect();
+            board.execute(String::from_utf8_lossy(&line).trim());
+        }
+
+        self.board.1.notify_all();
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for SimulatedBoard {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let board = self.lock();
+        let (mut board, _) = self
+            .board
+            .1
+            .wait_timeout_while(board, READ_TIMEOUT, |board| board.output.is_empty())
+            .unwrap_or_else(|e| e.into_inner());
+
+        if board.output.is_empty() {
+            if !board.state.encoder_publisher {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The simulated board sent nothing",
+                ));
+            }
+            let speed = format!("@5:{:.2};;\r\n", board.state.speed);
+            board.output.extend(speed.as_bytes());
+        }
+
+        let size = buffer.len().min(board.output.len());
+        for (byte, output) in buffer.iter_mut().zip(board.output.drain(..size)) {
+            *byte = output;
+        }
+        Ok(size)
+    }
+}
+
+impl NucleoTransport for SimulatedBoard {
+    fn try_clone(&self) -> io::Result<Box<dyn NucleoTransport>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::Message;
+
+    use super::*;
+
+    fn read_response(board: &mut SimulatedBoard) -> String {
+        let mut buffer = [0; 64];
+        let size = board.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).into_owned()
+    }
+
+    #[test]
+    fn test_replies_like_firmware() {
+        let mut board = SimulatedBoard::new();
+
+        board
+            .write_all(Message::Speed(0.3).to_string().as_bytes())
+            .unwrap();
+        assert_eq!(read_response(&mut board), "@1:ack;;\r\n");
+        // Received in several parts
+        board.write_all(b"#2:-12").unwrap();
+        board.write_all(b".50;;\r\n").unwrap();
+        assert_eq!(read_response(&mut board), "@2:ack;;\r\n");
+
+        board
+            .write_all(b"#2:40.00;;\r\n#1:fast;;\r\n#42:1;;\r\n")
+            .unwrap();
+        assert_eq!(
+            read_response(&mut board),
+            "@2:out of range;;\r\n@1:syntax error;;\r\n@42:unknown command;;\r\n"
+        );
+
+        let state = board.get_state();
+        assert_eq!(state.speed, 0.3);
+        assert_eq!(state.steering, -12.5);
+        assert_eq!(state.commands, 2);
+
+        board
+            .write_all(Message::Brake(3.0).to_string().as_bytes())
+            .unwrap();
+        assert!(board.get_state().braking);
+        assert_eq!(board.get_state().speed, 0.0);
+        assert_eq!(read_response(&mut board), "@3:ack;;\r\n");
+    }
+
+    #[test]
+    fn test_encoder_publisher() {
+        let mut board = SimulatedBoard::new();
+        let mut buffer = [0; 64];
+        assert_eq!(
+            board.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        board
+            .write_all(Message::EnableEncoderPublisher(true).to_string().as_bytes())
+            .unwrap();
+        board
+            .write_all(Message::Speed(0.2).to_string().as_bytes())
+            .unwrap();
+        assert_eq!(read_response(&mut board), "@5:ack;;\r\n@1:ack;;\r\n");
+        assert_eq!(read_response(&mut board), "@5:0.20;;\r\n");
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serialport::SerialPort;
+use tracing::info;
+
+use crate::serial::SimulatedBoard;
+
+/// Serial port of the nucleo board on the car
+pub const DEFAULT_NUCLEO_PORT: &str = "/dev/ttyACM0";
+const BAUD_RATE: u32 = 19200;
+/// Reads fail with [io::ErrorKind::TimedOut] once the board sent nothing for this long
+pub const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/**
+ * Byte link to the nucleo board. Reads wait at most [READ_TIMEOUT] for the board to send
+ * something, then fail with [io::ErrorKind::TimedOut].
+ */
+pub trait NucleoTransport: Read + Write + Send {
+    /// Another handle on the same link, e.g. to read from another thread
+    fn try_clone(&self) -> io::Result<Box<dyn NucleoTransport>>;
+}
+
+/// Serial port of the board, or a pseudo-terminal, e.g. one created by `socat`
+pub struct SerialTransport(Box<dyn SerialPort>);
+
+impl SerialTransport {
+    pub fn open(path: &str) -> io::Result<SerialTransport> {
+        let port = mio_serial::new(path, BAUD_RATE)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        Ok(SerialTransport(port))
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl NucleoTransport for SerialTransport {
+    fn try_clone(&self) -> io::Result<Box<dyn NucleoTransport>> {
+        Ok(Box::new(SerialTransport(self.0.try_clone()?)))
+    }
+}
+
+/// Board reachable over TCP, e.g. through `ser2net` or a simulator on another machine
+pub struct TcpTransport(TcpStream);
+
+impl TcpTransport {
+    pub fn connect(address: &str) -> io::Result<TcpTransport> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport(stream))
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buffer) {
+            // Depending on the platform, timeouts are reported as either
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, e))
+            }
+            Ok(0) if !buffer.is_empty() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The board closed the connection",
+            )),
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl NucleoTransport for TcpTransport {
+    fn try_clone(&self) -> io::Result<Box<dyn NucleoTransport>> {
+        Ok(Box::new(TcpTransport(self.0.try_clone()?)))
+    }
+}
+
+/**
+ * Opens the link to the board described by `link`: `simulated` for a board in memory,
+ * `tcp://host:port` for a board over TCP, or else the path of a serial port or pseudo-terminal.
+ */
+pub fn open_transport(link: &str) -> io::Result<Box<dyn NucleoTransport>> {
+    let transport: Box<dyn NucleoTransport> = if link == "simulated" {
+        Box::new(SimulatedBoard::new())
+    } else if let Some(address) = link.strip_prefix("tcp://") {
+        Box::new(TcpTransport::connect(address)?)
+    } else {
+        Box::new(SerialTransport::open(link)?)
+    };
+
+    info!("Connected to the nucleo board through {link}");
+    Ok(transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let board = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 64];
+            let size = stream.read(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], b"#1:0.20;;\r\n");
+            stream.write_all(b"@1:ack;;\r\n").unwrap();
+        });
+
+        let mut transport = open_transport(&format!("tcp://{address}")).unwrap();
+        transport.write_all(b"#1:0.20;;\r\n").unwrap();
+        let mut buffer = [0; 64];
+        let size = transport.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"@1:ack;;\r\n");
+
+        board.join().unwrap();
+        assert_eq!(
+            transport.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::panic;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+use tracing::{debug, error, info};
+
+use crate::serial::{Message, NucleoTransport};
+
+/// Sent whenever the car has to stop no matter what it was doing
+pub(super) const STOP_MESSAGES: [Message; 2] = [Message::Speed(0.0), Message::Brake(0.0)];
+
+/// Written directly, since the panicking thread might be the one writing to the board
+fn stop_car(transport: &mut dyn NucleoTransport) -> io::Result<()> {
+    for message in &STOP_MESSAGES {
+        transport.write_all(message.to_string().as_bytes())?;
+    }
+    transport.flush()
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    last_heartbeat: Instant,
+    /// Whether the car was stopped since the last heartbeat
+    tripped: bool,
+}
+
+/**
+ * Stops the car when the control loop does not send a heartbeat for a while, e.g. because
+ * it hangs. It runs on the writer thread of [SerialWriter](crate::serial::SerialWriter), which
+ * then drops the queued commands and sends [STOP_MESSAGES]. Until the next heartbeat, commands
+ * that would make the car move again are refused, so the control loop has to send its commands
+ * again once it is back.
+ */
+#[derive(Debug)]
+pub(super) struct Watchdog {
+    timeout: Duration,
+    state: Mutex<WatchdogState>,
+}
+
+impl Watchdog {
+    pub(super) fn new(timeout: Duration) -> Watchdog {
+        Watchdog {
+            timeout,
+            state: Mutex::new(WatchdogState {
+                last_heartbeat: Instant::now(),
+                tripped: false,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, WatchdogState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns whether the car was stopped since the last heartbeat
+    pub(super) fn heartbeat(&self) -> bool {
+        let mut state = self.lock();
+        state.last_heartbeat = Instant::now();
+        let tripped = std::mem::take(&mut state.tripped);
+        if tripped {
+            info!("The control loop is back");
+        }
+        tripped
+    }
+
+    fn is_refused(state: &WatchdogState, message: &Message) -> bool {
+        state.tripped && message.get_key() == Some(1) && *message != Message::Speed(0.0)
+    }
+
+    /**
+     * Queues the message for the writer thread, unless the car was stopped and the message
+     * would make it move again. Queued under the lock, so that it cannot slip in while the
+     * writer thread drops the queue.
+     */
+    pub(super) fn send(&self, sender: &Sender<Message>, message: Message) {
+        let state = self.lock();
+        if Watchdog::is_refused(&state, &message) {
+            debug!("The car is stopped until the next heartbeat, refused {message:?}");
+            return;
+        }
+        // The writer thread only stops once the writer is dropped, so this cannot fail
+        let _ = sender.send(message);
+    }
+
+    /// Whether the writer thread can send a message it took from the queue before a trip
+    pub(super) fn allows(&self, message: &Message) -> bool {
+        !Watchdog::is_refused(&self.lock(), message)
+    }
+
+    /// How long the writer thread can wait for messages before checking the heartbeat again
+    pub(super) fn get_wait(&self, now: Instant) -> Duration {
+        let state = self.lock();
+        if state.tripped {
+            self.timeout
+        } else {
+            (state.last_heartbeat + self.timeout).saturating_duration_since(now)
+        }
+    }
+
+    /**
+     * Trips when the heartbeat is late, dropping the commands queued in `receiver`.
+     * Returns true when the car has to be stopped.
+     */
+    pub(super) fn check(&self, receiver: &Receiver<Message>, now: Instant) -> bool {
+        let mut state = self.lock();
+        if state.tripped || now < state.last_heartbeat + self.timeout {
+            return false;
+        }
+
+        error!(
+            "No heartbeat from the control loop for {:?}, stopping the car",
+            now - state.last_heartbeat
+        );
+        state.tripped = true;
+        let dropped = receiver.try_iter().count();
+        if dropped > 0 {
+            debug!("Dropped {dropped} queued commands");
+        }
+        true
+    }
+}
+
+/// Stops the car whenever a thread panics, before the usual panic message is printed
+pub fn install_panic_hook(transport: Box<dyn NucleoTransport>) {
+    let transport = Mutex::new(transport);
+    let previous = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let mut transport = transport.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = stop_car(transport.as_mut()) {
+            eprintln!("Failed to stop the car after a panic: {e}");
+        }
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::serial::{BoardState, SerialWriter, SimulatedBoard};
+
+    use super::*;
+
+    /// Generous, the board usually gets there in a few milliseconds
+    const BOARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn wait_for(board: &SimulatedBoard, condition: impl Fn(&BoardState) -> bool) {
+        let deadline = Instant::now() + BOARD_TIMEOUT;
+        while !condition(&board.get_state()) {
+            assert!(
+                Instant::now() < deadline,
+                "Timed out with the board in {:?}",
+                board.get_state()
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_gates_commands() {
+        let watchdog = Watchdog::new(Duration::from_secs(1));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let now = Instant::now();
+
+        watchdog.send(&sender, Message::Speed(0.3));
+        watchdog.send(&sender, Message::Steer(5.0));
+        assert!(!watchdog.check(&receiver, now));
+        assert_eq!(receiver.len(), 2);
+
+        // The queued commands are dropped
+        assert!(watchdog.check(&receiver, now + Duration::from_secs(2)));
+        assert!(receiver.is_empty());
+        assert!(!watchdog.check(&receiver, now + Duration::from_secs(3)));
+
+        watchdog.send(&sender, Message::Speed(0.3));
+        watchdog.send(&sender, Message::Raw("#1:0.30;;".to_string()));
+        assert!(receiver.is_empty());
+        assert!(!watchdog.allows(&Message::Speed(0.3)));
+        watchdog.send(&sender, Message::Speed(0.0));
+        watchdog.send(&sender, Message::Steer(5.0));
+        assert_eq!(receiver.len(), 2);
+
+        assert!(watchdog.heartbeat());
+        assert!(!watchdog.heartbeat());
+        assert!(watchdog.allows(&Message::Speed(0.3)));
+    }
+
+    #[test]
+    fn test_stops_without_heartbeat() {
+        let board = SimulatedBoard::new();
+        let writer =
+            SerialWriter::start_with_watchdog(Box::new(board.clone()), Duration::from_millis(200))
+                .unwrap();
+
+        writer.send(Message::Speed(0.3));
+        wait_for(&board, |state| state.speed == 0.3);
+        wait_for(&board, |state| state.braking);
+
+        // Commands are applied in order, so the speed was refused once the steering is applied
+        writer.send(Message::Speed(0.3));
+        writer.send(Message::Steer(5.0));
+        wait_for(&board, |state| state.steering == 5.0);
+        assert_eq!(board.get_state().speed, 0.0);
+
+        assert!(writer.heartbeat());
+        writer.send(Message::Speed(0.2));
+        wait_for(&board, |state| state.speed == 0.2);
+
+        writer.stop();
+        assert!(board.get_state().braking);
+        assert_eq!(board.get_state().speed, 0.0);
+    }
+}
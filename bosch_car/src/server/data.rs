@@ -12,6 +12,33 @@ pub struct MovingObstaclePos {
     angle: (f32, f32),
 }
 
+impl MovingObstaclePos {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    #[allow(
+        dead_code,
+        reason = "the tracker times positions when they are received"
+    )]
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// In meters, in the server's frame (y pointing down)
+    pub fn get_position(&self) -> (f32, f32) {
+        self.position
+    }
+
+    #[allow(
+        dead_code,
+        reason = "the tracker estimates the heading from the positions"
+    )]
+    pub fn get_angle(&self) -> (f32, f32) {
+        self.angle
+    }
+}
+
 impl Display for MovingObstaclePos {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -50,6 +77,9 @@ pub(super) struct TrafficLight {
     pub color: TrafficLightColor,
 }
 
+/// The server sends the colors of the traffic lights 1 to 4
+pub const TRAFFIC_LIGHT_COUNT: u8 = 4;
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct TrafficLightsStatus(
     pub TrafficLightColor,
@@ -72,6 +102,13 @@ impl TrafficLightsStatus {
     fn as_slice(&self) -> [TrafficLightColor; 4] {
         [self.0, self.1, self.2, self.3]
     }
+
+    /// Color of the traffic light with the server's `id`, starting from 1
+    pub fn get(&self, id: u8) -> Option<TrafficLightColor> {
+        self.as_slice()
+            .get(usize::from(id).checked_sub(1)?)
+            .copied()
+    }
 }
 
 // endregion Traffic Lights
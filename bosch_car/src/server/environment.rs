@@ -1,16 +1,25 @@
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
 use tracing::info;
 
 use crate::server::data::EnvironmentalObstacle;
-use crate::server::utils::{check_authentication, listen_for_port, sign_message, CAR_ID};
+use crate::server::supervisor::{supervise, Backoff, StatusHandle};
+use crate::server::utils::{
+    check_authentication, connect, listen_for_port, sign_message, with_timeout, CAR_ID,
+    CONNECTION_TIMEOUT,
+};
 
-async fn establish_server_connection(server_address: &String) -> std::io::Result<()> {
+/// Where the server announces the port it receives obstacles on
+pub(super) const DISCOVERY_ADDRESS: &str = "0.0.0.0:25565";
+
+async fn establish_server_connection(server_address: &str) -> std::io::Result<UdpSocket> {
     let public_key = RsaPublicKey::from_public_key_pem(include_str!("publickey_server.pem"))
         .map_err(|_| {
             std::io::Error::new(
@@ -26,43 +35,63 @@ async fn establish_server_connection(server_address: &String) -> std::io::Result
             )
         })?;
 
-    let socket = UdpSocket::bind(&server_address).await?;
+    let socket = connect(server_address).await?;
 
     // Send car id
     let message = CAR_ID.as_bytes();
     let signature = sign_message(message, private_key)?;
     socket.send(message).await?;
-    sleep(Duration::from_millis(100));
+    sleep(Duration::from_millis(100)).await;
     socket.send(signature.as_slice()).await?;
 
-    check_authentication(public_key, socket).await?;
+    check_authentication(public_key, &socket).await?;
     info!("Connected to server address {server_address}");
 
-    Ok(())
+    Ok(socket)
 }
 
 async fn send_data_to_environment_server(
-    server_address: &String,
-    mut rx: Receiver<EnvironmentalObstacle>,
+    server_socket: &UdpSocket,
+    rx: &Mutex<Receiver<EnvironmentalObstacle>>,
+    status: &StatusHandle,
 ) -> std::io::Result<()> {
-    let server_socket = UdpSocket::bind(server_address).await?;
+    let mut rx = rx.lock().await;
 
     while let Some(environmental_obstacle) = rx.recv().await {
         let serialized_obstacle = serde_json::to_string(&environmental_obstacle)?;
         server_socket.send(serialized_obstacle.as_ref()).await?;
-        sleep(Duration::from_millis(100));
+        status.record_message(Instant::now());
+        sleep(Duration::from_millis(100)).await;
     }
 
     Ok(())
 }
 
-pub async fn run_sender(rx: Receiver<EnvironmentalObstacle>) -> std::io::Result<()> {
-    let server_address = listen_for_port("0.0.0.0:25565").await?;
+async fn connect_and_send(
+    rx: Arc<Mutex<Receiver<EnvironmentalObstacle>>>,
+    status: StatusHandle,
+) -> std::io::Result<()> {
+    let server_address = listen_for_port(DISCOVERY_ADDRESS).await?;
 
     // Verify the server authentication and acknowledge connection
-    establish_server_connection(&server_address).await?;
+    let socket = with_timeout(
+        CONNECTION_TIMEOUT,
+        establish_server_connection(&server_address),
+    )
+    .await?;
+    status.set_connected(true);
 
-    send_data_to_environment_server(&server_address, rx).await?;
+    send_data_to_environment_server(&socket, &rx, &status).await
+}
 
-    Ok(())
+/**
+ * Sends the obstacles to the environment server, finding and authenticating it again whenever
+ * sending fails. Obstacles found meanwhile wait in the channel.
+ */
+pub async fn run_sender(rx: Receiver<EnvironmentalObstacle>, status: StatusHandle) {
+    let rx = Arc::new(Mutex::new(rx));
+    supervise("environment", status.clone(), Backoff::default(), || {
+        connect_and_send(rx.clone(), status.clone())
+    })
+    .await;
 }
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use rsa::pkcs8::DecodePublicKey;
 use rsa::RsaPublicKey;
 use tokio::net::UdpSocket;
@@ -5,10 +7,21 @@ use tokio::sync::mpsc::Sender;
 use tracing::{error, info};
 
 use crate::server::data::ServerCarPos;
-use crate::server::utils::{check_authentication, listen_for_port, CAR_ID};
+use crate::server::supervisor::{supervise, Backoff, StatusHandle};
+use crate::server::utils::{
+    check_authentication, connect, listen_for_port, with_timeout, CAR_ID, CONNECTION_TIMEOUT,
+};
 use crate::server::ServerData;
 
-async fn establish_server_connection(server_address: &String) -> std::io::Result<()> {
+/**
+ * Where the server announces the port it sends positions from, the beacon port of the BFMC
+ * localisation system. Not 50009, which is where the moving obstacle sends its position.
+ */
+pub(super) const DISCOVERY_ADDRESS: &str = "0.0.0.0:12345";
+/// Without a position for this long, the connection is considered lost
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn establish_server_connection(server_address: &str) -> std::io::Result<UdpSocket> {
     // Parse public key
     let public_key = RsaPublicKey::from_public_key_pem(include_str!("publickey_server.pem"))
         .map_err(|_| {
@@ -18,15 +31,15 @@ async fn establish_server_connection(server_address: &String) -> std::io::Result
             )
         })?;
 
-    let socket = UdpSocket::bind(&server_address).await?;
+    let socket = connect(server_address).await?;
 
     // Send car id
     socket.send(CAR_ID.as_bytes()).await?;
 
-    check_authentication(public_key, socket).await?;
+    check_authentication(public_key, &socket).await?;
     info!("Connected to server address {server_address}");
 
-    Ok(())
+    Ok(socket)
 }
 
 async fn parse_position(socket: &UdpSocket) -> std::io::Result<ServerCarPos> {
@@ -36,29 +49,52 @@ async fn parse_position(socket: &UdpSocket) -> std::io::Result<ServerCarPos> {
     Ok(obstacle)
 }
 
+/// Receives positions until the server goes silent, or nobody needs them anymore
 async fn run_localisation_listener(
-    server_address: String,
-    sender: Sender<ServerData>,
+    socket: &UdpSocket,
+    sender: &Sender<ServerData>,
+    status: &StatusHandle,
 ) -> std::io::Result<()> {
-    let socket = UdpSocket::bind(server_address).await?;
-
     loop {
-        match parse_position(&socket).await {
-            Ok(pos) => sender.send(ServerData::CarPos(pos)).await.unwrap(),
-            Err(e) => error!("Error occurred while receiving/parsing data: {}", e),
+        match with_timeout(SILENCE_TIMEOUT, parse_position(socket)).await {
+            Ok(pos) => {
+                status.record_message(Instant::now());
+                if sender.send(ServerData::CarPos(pos)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                status.record_error(&e);
+                error!("Error occurred while parsing data: {}", e);
+            }
+            Err(e) => return Err(e),
         }
     }
 }
 
-pub async fn run_listener(sender: Sender<ServerData>) -> std::io::Result<()> {
+async fn connect_and_listen(
+    sender: Sender<ServerData>,
+    status: StatusHandle,
+) -> std::io::Result<()> {
     // First receive the port to listen on
-    let server_address = listen_for_port("0.0.0.0:50009").await?;
+    let server_address = listen_for_port(DISCOVERY_ADDRESS).await?;
 
     // Verify the server authentication and acknowledge connection
-    establish_server_connection(&server_address).await?;
+    let socket = with_timeout(
+        CONNECTION_TIMEOUT,
+        establish_server_connection(&server_address),
+    )
+    .await?;
+    status.set_connected(true);
 
     // Listen for robot position
-    run_localisation_listener(server_address, sender).await?;
+    run_localisation_listener(&socket, &sender, &status).await
+}
 
-    Ok(())
+/// Receives the position of the car, finding and authenticating the server again whenever it is lost
+pub async fn run_listener(sender: Sender<ServerData>, status: StatusHandle) {
+    supervise("localisation", status.clone(), Backoff::default(), || {
+        connect_and_listen(sender.clone(), status.clone())
+    })
+    .await;
 }
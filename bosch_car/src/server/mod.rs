@@ -10,10 +10,14 @@ pub mod data;
 mod environment;
 mod localisation;
 mod moving_obstacle;
+pub mod replay;
 pub mod steering_wheel;
+mod supervisor;
 mod traffic_lights;
 mod utils;
 
+pub use self::supervisor::*;
+
 #[derive(Debug)]
 pub enum ServerData {
     CarPos(ServerCarPos),
@@ -21,20 +25,58 @@ pub enum ServerData {
     MovingObstacle(MovingObstaclePos),
 }
 
-pub fn run_server_listeners() -> Receiver<ServerData> {
+/// Listens to the competition servers, reconnecting to them as needed and reporting to `status`
+pub fn run_server_listeners(status: &ServerStatus) -> Receiver<ServerData> {
     let (tx, rx) = mpsc::channel(64);
 
-    task::spawn(localisation::run_listener(tx.clone()));
-    task::spawn(traffic_lights::run_listener(tx.clone()));
-    task::spawn(moving_obstacle::run_listener(tx));
+    task::spawn(localisation::run_listener(
+        tx.clone(),
+        status.localisation.clone(),
+    ));
+    task::spawn(traffic_lights::run_listener(
+        tx.clone(),
+        status.traffic_lights.clone(),
+    ));
+    task::spawn(moving_obstacle::run_listener(
+        tx,
+        status.moving_obstacle.clone(),
+    ));
 
     rx
 }
 
-pub fn environment_server_publisher() -> Sender<EnvironmentalObstacle> {
+pub fn environment_server_publisher(status: &ServerStatus) -> Sender<EnvironmentalObstacle> {
     let (tx, rx) = mpsc::channel(64);
 
-    task::spawn(environment::run_sender(rx));
+    task::spawn(environment::run_sender(rx, status.environment.clone()));
 
     tx
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn test_clients_bind_different_ports() {
+        let addresses = [
+            localisation::DISCOVERY_ADDRESS,
+            traffic_lights::BIND_ADDRESS,
+            moving_obstacle::BIND_ADDRESS,
+            environment::DISCOVERY_ADDRESS,
+        ];
+        let ports: Vec<u16> = addresses
+            .iter()
+            .map(|address| address.parse::<SocketAddr>().unwrap().port())
+            .collect();
+
+        for (index, port) in ports.iter().enumerate() {
+            assert!(
+                !ports[index + 1..].contains(port),
+                "Port {port} is used twice in {addresses:?}"
+            );
+        }
+    }
+}
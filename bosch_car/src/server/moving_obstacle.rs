@@ -1,10 +1,16 @@
+use std::time::Instant;
+
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::Sender;
 use tracing::error;
 
 use crate::server::data::MovingObstaclePos;
+use crate::server::supervisor::{supervise, Backoff, StatusHandle};
 use crate::server::ServerData;
 
+/// Where the moving obstacle sends its position
+pub(super) const BIND_ADDRESS: &str = "0.0.0.0:50009";
+
 async fn parse_data(socket: &UdpSocket) -> std::io::Result<MovingObstaclePos> {
     let mut buffer = [0; 4096];
     let size = socket.recv(&mut buffer).await?;
@@ -12,16 +18,37 @@ async fn parse_data(socket: &UdpSocket) -> std::io::Result<MovingObstaclePos> {
     Ok(obstacle)
 }
 
-pub async fn run_listener(sender: Sender<ServerData>) -> std::io::Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:50009").await?;
+async fn listen(sender: &Sender<ServerData>, status: &StatusHandle) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(BIND_ADDRESS).await?;
+    status.set_connected(true);
 
     loop {
         match parse_data(&socket).await {
-            Ok(obstacle) => sender
-                .send(ServerData::MovingObstacle(obstacle))
-                .await
-                .unwrap(),
-            Err(e) => error!("Error occurred while receiving/parsing data: {}", e),
+            Ok(obstacle) => {
+                status.record_message(Instant::now());
+                if sender
+                    .send(ServerData::MovingObstacle(obstacle))
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                status.record_error(&e);
+                error!("Error occurred while parsing data: {}", e);
+            }
+            Err(e) => return Err(e),
         }
     }
 }
+
+pub async fn run_listener(sender: Sender<ServerData>, status: StatusHandle) {
+    supervise(
+        "moving obstacle",
+        status.clone(),
+        Backoff::default(),
+        || listen(&sender, &status),
+    )
+    .await;
+}
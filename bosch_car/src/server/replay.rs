@@ -0,0 +1,331 @@
+use std::io::{self, BufRead};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::serial;
+use crate::serial::{Message, NucleoTransport};
+
+/// A line of a recording of [run_steering_wheel_server](crate::server::steering_wheel::run_steering_wheel_server)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    /// Since the previous message of the recording
+    pub delay: Duration,
+    pub message: Message,
+}
+
+/// Parses a line like `250|#2:10.00;;`
+pub fn parse_recorded_message(line: &str) -> Result<RecordedMessage, String> {
+    let (delay, message) = line
+        .split_once('|')
+        .ok_or_else(|| format!("Missing delay in \"{}\"", line.trim()))?;
+    let delay = delay
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid delay in \"{}\": {e}", line.trim()))?;
+
+    Ok(RecordedMessage {
+        delay: Duration::from_millis(delay),
+        message: message.parse()?,
+    })
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    paused: bool,
+    stopped: bool,
+}
+
+/// Pauses, resumes or stops a replay from another thread
+#[derive(Debug, Clone, Default)]
+pub struct ReplayControl(Arc<(Mutex<ControlState>, Condvar)>);
+
+impl ReplayControl {
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        self.0 .0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, update: impl FnOnce(&mut ControlState)) {
+        update(&mut self.lock());
+        self.0 .1.notify_all();
+    }
+
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| state.paused = false);
+    }
+
+    pub fn stop(&self) {
+        self.update(|state| state.stopped = true);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /**
+     * Waits until `deadline`, or until the replay is stopped, returning false then.
+     * Returns early when the replay gets paused, with the state locked.
+     */
+    fn wait_until(&self, deadline: Instant) -> (bool, MutexGuard<'_, ControlState>) {
+        let mut state = self.lock();
+        loop {
+            if state.stopped {
+                return (false, state);
+            }
+            let now = Instant::now();
+            if state.paused || now >= deadline {
+                return (true, state);
+            }
+            state = self
+                .0
+                 .1
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Waits while the replay is paused, returning false if it gets stopped meanwhile
+    fn wait_while_paused(&self) -> bool {
+        let state = self
+            .0
+             .1
+            .wait_while(self.lock(), |state| state.paused && !state.stopped)
+            .unwrap_or_else(|e| e.into_inner());
+        !state.stopped
+    }
+}
+
+/**
+ * Re-sends recorded messages to the board with the timing they were recorded with.
+ * While paused the car is stopped, then its last speed and steering are sent again.
+ */
+pub struct Replayer<'a> {
+    nucleo: &'a mut dyn NucleoTransport,
+    /// How much faster than recorded the messages are sent
+    rate: f64,
+    control: ReplayControl,
+    speed: f32,
+    steering: f32,
+}
+
+impl<'a> Replayer<'a> {
+    pub fn new(nucleo: &'a mut dyn NucleoTransport, rate: f64, control: ReplayControl) -> Self {
+        Replayer {
+            nucleo,
+            rate,
+            control,
+            speed: 0.0,
+            steering: 0.0,
+        }
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        match *message {
+            Message::Speed(speed) => self.speed = speed,
+            Message::Steer(steering) => self.steering = steering,
+            Message::Brake(steering) => {
+                self.speed = 0.0;
+                self.steering = steering;
+            }
+            _ => {}
+        }
+        serial::send_blocking(self.nucleo, message)
+    }
+
+    fn stop_car(&mut self) -> io::Result<()> {
+        serial::send_blocking(self.nucleo, &Message::Speed(0.0))?;
+        serial::send_blocking(self.nucleo, &Message::Brake(self.steering))
+    }
+
+    /**
+     * Plays the recording read from `reader` until its end, an error, or until the replay is
+     * stopped. The car is stopped in every case.
+     */
+    pub fn play(&mut self, reader: impl BufRead) -> io::Result<()> {
+        let result = self.play_messages(reader);
+
+        if let Err(e) = self.stop_car() {
+            error!("Failed to stop the car after the replay: {e}");
+        }
+        result
+    }
+
+    fn play_messages(&mut self, reader: impl BufRead) -> io::Result<()> {
+        // Sending takes time too, so messages are scheduled from the start of the replay
+        let mut deadline = Instant::now();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |e: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {}: {e}", index + 1),
+                )
+            };
+            let recorded = parse_recorded_message(&line).map_err(invalid)?;
+
+            deadline = Duration::try_from_secs_f64(recorded.delay.as_secs_f64() / self.rate)
+                .ok()
+                .and_then(|delay| deadline.checked_add(delay))
+                .ok_or_else(|| invalid(format!("Delay too long at a rate of {}", self.rate)))?;
+            if !self.wait_until(&mut deadline)? {
+                info!("Replay stopped");
+                return Ok(());
+            }
+            self.send(&recorded.message)?;
+        }
+
+        info!("Replay finished");
+        Ok(())
+    }
+
+    /// Waits until `deadline`, which is delayed by the time spent paused
+    fn wait_until(&mut self, deadline: &mut Instant) -> io::Result<bool> {
+        loop {
+            let (running, state) = self.control.wait_until(*deadline);
+            if !running {
+                return Ok(false);
+            }
+            if !state.paused {
+                return Ok(true);
+            }
+            drop(state);
+
+            info!("Replay paused");
+            let paused = Instant::now();
+            serial::send_blocking(self.nucleo, &Message::Brake(self.steering))?;
+            if !self.control.wait_while_paused() {
+                return Ok(false);
+            }
+
+            info!("Replay resumed");
+            *deadline += paused.elapsed();
+            serial::send_blocking(self.nucleo, &Message::Steer(self.steering))?;
+            serial::send_blocking(self.nucleo, &Message::Speed(self.speed))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::serial::{BoardState, SimulatedBoard};
+
+    use super::*;
+
+    const RECORDING: &str = "0|#2:10.00;;\n20|#1:0.30;;\n\n40|#2:-5.00;;\n";
+
+    #[test]
+    fn test_parse_recorded_message() {
+        assert_eq!(
+            parse_recorded_message("250|#2:10.00;;"),
+            Ok(RecordedMessage {
+                delay: Duration::from_millis(250),
+                message: Message::Steer(10.0)
+            })
+        );
+        assert!(parse_recorded_message("#2:10.00;;").is_err());
+        assert!(parse_recorded_message("soon|#2:10.00;;").is_err());
+        assert!(parse_recorded_message("10|#2:left;;").is_err());
+    }
+
+    /// Generous, the board usually gets there in a few milliseconds
+    const BOARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn wait_for(board: &SimulatedBoard, condition: impl Fn(&BoardState) -> bool) {
+        let deadline = Instant::now() + BOARD_TIMEOUT;
+        while !condition(&board.get_state()) {
+            assert!(
+                Instant::now() < deadline,
+                "Timed out with the board in {:?}",
+                board.get_state()
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_replay_stops_car() {
+        let board = SimulatedBoard::new();
+        let mut nucleo = board.clone();
+
+        Replayer::new(&mut nucleo, 1.0, ReplayControl::default())
+            .play(RECORDING.as_bytes())
+            .unwrap();
+
+        let state = board.get_state();
+        assert_eq!(state.steering, -5.0);
+        assert!(state.braking);
+        assert_eq!(state.commands, 5);
+
+        // Stopped on the invalid line, before sending its speed
+        let recording = "0|#1:0.30;;\n10|#1:fast;;\n10|#1:0.40;;\n";
+        let result =
+            Replayer::new(&mut nucleo, 1.0, ReplayControl::default()).play(recording.as_bytes());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(board.get_state().speed, 0.0);
+        assert!(board.get_state().braking);
+
+        let recording = format!("0|#1:0.30;;\n{}|#1:0.40;;\n", u64::MAX);
+        let result =
+            Replayer::new(&mut nucleo, 0.001, ReplayControl::default()).play(recording.as_bytes());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(board.get_state().braking);
+    }
+
+    #[test]
+    fn test_replay_rate() {
+        // Recorded over a second
+        let recording = "0|#1:0.30;;\n1000|#2:5.00;;\n";
+        let mut nucleo = SimulatedBoard::new();
+        let start = Instant::now();
+
+        Replayer::new(&mut nucleo, 10.0, ReplayControl::default())
+            .play(recording.as_bytes())
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100));
+        // Far from the time it was recorded over, even on a slow machine
+        assert!(elapsed < Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_pause() {
+        let board = SimulatedBoard::new();
+        let control = ReplayControl::default();
+        let replay = {
+            let mut nucleo = board.clone();
+            let control = control.clone();
+            // The steering is only sent once the replay is stopped
+            thread::spawn(move || {
+                Replayer::new(&mut nucleo, 1.0, control)
+                    .play("0|#1:0.30;;\n3600000|#2:5.00;;\n".as_bytes())
+            })
+        };
+        wait_for(&board, |state| state.speed == 0.3);
+
+        control.pause();
+        assert!(control.is_paused());
+        // Stopped while paused
+        wait_for(&board, |state| state.braking);
+        assert_eq!(board.get_state().speed, 0.0);
+
+        control.resume();
+        wait_for(&board, |state| state.speed == 0.3 && !state.braking);
+
+        control.stop();
+        replay.join().unwrap().unwrap();
+        assert!(board.get_state().braking);
+        assert_eq!(board.get_state().steering, 0.0);
+    }
+}
@@ -1,13 +1,17 @@
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::time::Instant;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
 
 use serde::Deserialize;
-use shared::math::AlmostEquals;
 use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
 
-use crate::serial;
-use crate::serial::Message;
+use crate::serial::{Message, SerialWriter};
+
+/// Commands closer than this to the last sent one are not sent again
+const SPEED_TOLERANCE: f32 = 0.01;
+const STEERING_TOLERANCE: f32 = 3.0; // degrees
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 struct SteeringWheelData {
@@ -17,72 +21,298 @@ struct SteeringWheelData {
     record: bool,
 }
 
-pub async fn run_steering_wheel_server(path: &str) -> std::io::Result<()> {
-    let udp_socket = UdpSocket::bind("10.1.0.200:40000").await?;
+/// How the steering wheel drives the car. Speeds are in meters per second and angles in degrees.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TeleopConfig {
+    /// Where the steering wheel sends its data
+    pub bind_address: String,
+    /// Speed as soon as the pedals leave the deadzone
+    pub min_speed: f32,
+    /// Speed with a pedal fully pressed
+    pub max_speed: f32,
+    /// Pedal positions, from 0 to 1, below which the car does not move
+    pub deadzone: f32,
+    /// Factor from the angle of the wheel to the steering angle of the car
+    pub steering_scale: f32,
+    pub max_steering: f32,
+    /**
+     * From 0 for a linear response to 1 for a cubic one, which is more precise around the
+     * center and less at the ends
+     */
+    pub speed_expo: f32,
+    pub steering_expo: f32,
+    /// Without data from the steering wheel for this long, in seconds, the car stops
+    pub dead_man_timeout: f64,
+    /// At most this many speed and steering commands are sent each second
+    pub command_rate: f64,
+    /// Where the commands are written while the steering wheel asks to record
+    pub record_path: Option<String>,
+}
+
+impl Default for TeleopConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "10.1.0.200:40000".to_string(),
+            min_speed: 0.1,
+            max_speed: 0.2,
+            deadzone: 0.05,
+            steering_scale: 1.0,
+            max_steering: 25.0,
+            speed_expo: 0.0,
+            steering_expo: 0.0,
+            dead_man_timeout: 0.5,
+            command_rate: 20.0,
+            record_path: None,
+        }
+    }
+}
+
+/// Blends `value`, from -1 to 1, between a linear and a cubic curve
+fn apply_expo(value: f32, expo: f32) -> f32 {
+    let expo = expo.clamp(0.0, 1.0);
+    (1.0 - expo) * value + expo * value.powi(3)
+}
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(path)
-        .unwrap();
+impl TeleopConfig {
+    /// Speed of the car for the difference between the pedals, from -1 to 1
+    pub fn get_speed(&self, throttle: f32) -> f32 {
+        if throttle.abs() < self.deadzone {
+            return 0.0;
+        }
 
-    let mut last_speed_percentage = 0.0;
-    let mut last_steer = 0.0;
-    let mut last_message_instant = Instant::now();
+        let range = (1.0 - self.deadzone).max(f32::EPSILON);
+        let pressed = ((throttle.abs() - self.deadzone) / range).min(1.0);
+        let speed = self.min_speed
+            + (self.max_speed - self.min_speed) * apply_expo(pressed, self.speed_expo);
+        speed.copysign(throttle)
+    }
 
-    let mut append_message_to_file = |message: &Message| {
+    /// Steering angle of the car for the angle of the wheel
+    pub fn get_steering(&self, angle: f32) -> f32 {
+        if self.max_steering <= 0.0 {
+            return 0.0;
+        }
+
+        let steering = (angle * self.steering_scale / self.max_steering).clamp(-1.0, 1.0);
+        apply_expo(steering, self.steering_expo) * self.max_steering
+    }
+}
+
+/**
+ * Turns the data of the steering wheel into commands, sending at most one speed and one steering
+ * command per tick, and stopping the car when the steering wheel goes silent
+ */
+struct Teleop {
+    config: TeleopConfig,
+    /// Last data of the steering wheel, as (speed, steering), and when it was received
+    target: Option<(Instant, f32, f32)>,
+    speed: Option<f32>,
+    steering: Option<f32>,
+    stopped: bool,
+}
+
+impl Teleop {
+    fn new(config: TeleopConfig) -> Teleop {
+        Teleop {
+            config,
+            target: None,
+            speed: None,
+            steering: None,
+            stopped: true,
+        }
+    }
+
+    fn handle_data(&mut self, data: &SteeringWheelData, now: Instant) {
+        let throttle = data.acceleration_percentage - data.clutch_percentage;
+        self.target = Some((
+            now,
+            self.config.get_speed(throttle),
+            self.config.get_steering(data.steering_angle),
+        ));
+    }
+
+    fn tick(&mut self, now: Instant) -> Vec<Message> {
+        let timeout = Duration::from_secs_f64(self.config.dead_man_timeout);
+        let target = self
+            .target
+            .filter(|(update, _, _)| now.saturating_duration_since(*update) < timeout);
+
+        let Some((_, speed, steering)) = target else {
+            if self.stopped {
+                return Vec::new();
+            }
+            warn!("No data from the steering wheel, stopping the car");
+            self.stopped = true;
+            self.speed = Some(0.0);
+            return vec![
+                Message::Speed(0.0),
+                Message::Brake(self.steering.unwrap_or(0.0)),
+            ];
+        };
+        self.stopped = false;
+
+        let mut messages = Vec::new();
+        if self
+            .steering
+            .is_none_or(|last| (steering - last).abs() >= STEERING_TOLERANCE)
+        {
+            messages.push(Message::Steer(steering));
+            self.steering = Some(steering);
+        }
+        if self
+            .speed
+            .is_none_or(|last| (speed - last).abs() >= SPEED_TOLERANCE)
+        {
+            messages.push(Message::Speed(speed));
+            self.speed = Some(speed);
+        }
+
+        messages
+    }
+}
+
+/// Writes the commands as `elapsed_ms|message` lines, the delay being since the previous one
+struct Recorder {
+    file: BufWriter<File>,
+    last_message: Instant,
+}
+
+impl Recorder {
+    fn create(path: &str) -> io::Result<Recorder> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Recorder {
+            file: BufWriter::new(file),
+            last_message: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, message: &Message, now: Instant) -> io::Result<()> {
         writeln!(
-            file,
+            self.file,
             "{}|{}",
-            last_message_instant.elapsed().as_millis(),
+            now.saturating_duration_since(self.last_message).as_millis(),
             message.to_string().trim()
-        )
-        .unwrap();
+        )?;
+        self.last_message = now;
+        self.file.flush()
+    }
+}
+
+/**
+ * Drives the car with the steering wheel, which sends its state as JSON over UDP,
+ * until receiving fails
+ */
+pub async fn run_steering_wheel_server(
+    config: &TeleopConfig,
+    nucleo: &SerialWriter,
+) -> io::Result<()> {
+    let udp_socket = UdpSocket::bind(&config.bind_address).await?;
+    let mut recorder = config
+        .record_path
+        .as_deref()
+        .map(Recorder::create)
+        .transpose()?;
+    info!("Waiting for the steering wheel on {}", config.bind_address);
 
-        last_message_instant = Instant::now();
-    };
+    let mut teleop = Teleop::new(config.clone());
+    let mut recording = false;
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.command_rate));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut buffer = [0; 4096];
 
     loop {
-        let mut buffer = [0; 4096];
-        let size = udp_socket.recv(&mut buffer).await?;
-
-        let data: SteeringWheelData = serde_json::from_slice(&buffer[0..size])?;
-        if data.record {
-            println!("Recording");
-        } else {
-            println!("Not recording");
-        }
+        tokio::select! {
+            result = udp_socket.recv(&mut buffer) => {
+                let size = result?;
+                let data: SteeringWheelData = match serde_json::from_slice(&buffer[..size]) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        warn!("Invalid data from the steering wheel: {e}");
+                        continue;
+                    }
+                };
 
-        // Do not send the same message twice
-        if !last_steer.almost_equals(data.steering_angle, 3.0) {
-            let message = Message::Steer(data.steering_angle);
-            if data.record {
-                append_message_to_file(&message);
+                if data.record != recording {
+                    recording = data.record;
+                    info!("{} recording", if recording { "Started" } else { "Stopped" });
+                }
+                teleop.handle_data(&data, Instant::now());
+            }
+            _ = interval.tick() => {
+                let now = Instant::now();
+                for message in teleop.tick(now) {
+                    if let Some(recorder) = recorder.as_mut().filter(|_| recording) {
+                        if let Err(e) = recorder.record(&message, now) {
+                            warn!("Failed to record \"{}\": {e}", message.to_string().trim());
+                        }
+                    }
+                    nucleo.send(message);
+                }
             }
-            serial::send_blocking(message)?;
-            last_steer = data.steering_angle;
         }
+    }
+}
 
-        let speed_percentage = data.acceleration_percentage - data.clutch_percentage;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if !last_speed_percentage.almost_equals(speed_percentage, 0.01) {
-            let message = if speed_percentage.abs() < 0.05 {
-                println!("Stopping");
-                Message::Speed(0.0)
-            } else {
-                println!("Driving");
-                let speed = speed_percentage * 0.1;
-                let min_speed = if speed_percentage > 0.0 { 0.1 } else { -0.1 };
-                let final_speed = speed + min_speed;
+    fn get_data(throttle: f32, steering_angle: f32) -> SteeringWheelData {
+        SteeringWheelData {
+            acceleration_percentage: throttle,
+            clutch_percentage: 0.0,
+            steering_angle,
+            record: false,
+        }
+    }
 
-                Message::Speed(final_speed)
-            };
+    #[test]
+    fn test_mapping() {
+        let config = TeleopConfig {
+            speed_expo: 1.0,
+            ..Default::default()
+        };
 
-            if data.record {
-                append_message_to_file(&message);
-            }
-            serial::send_blocking(message)?;
-            last_speed_percentage = speed_percentage;
-        }
+        assert_eq!(config.get_speed(0.03), 0.0);
+        assert_eq!(config.get_speed(1.0), 0.2);
+        assert_eq!(config.get_speed(-1.0), -0.2);
+        // Cubic, so half the pedal only gives an eighth of the speed range
+        assert!((config.get_speed(0.525) - 0.1125).abs() < 1e-6);
+
+        assert_eq!(config.get_steering(10.0), 10.0);
+        assert_eq!(config.get_steering(-90.0), -25.0);
+    }
+
+    #[test]
+    fn test_dead_man_and_rate_limiting() {
+        let mut teleop = Teleop::new(TeleopConfig::default());
+        let now = Instant::now();
+        assert!(teleop.tick(now).is_empty());
+
+        // Only the last data of a tick is sent
+        teleop.handle_data(&get_data(1.0, 0.0), now);
+        teleop.handle_data(&get_data(1.0, 10.0), now);
+        assert_eq!(
+            teleop.tick(now),
+            vec![Message::Steer(10.0), Message::Speed(0.2)]
+        );
+        teleop.handle_data(&get_data(1.0, 11.0), now);
+        assert!(teleop.tick(now).is_empty());
+
+        let later = now + Duration::from_millis(600);
+        assert_eq!(
+            teleop.tick(later),
+            vec![Message::Speed(0.0), Message::Brake(10.0)]
+        );
+        assert!(teleop.tick(later).is_empty());
+
+        teleop.handle_data(&get_data(1.0, 10.0), later);
+        assert_eq!(teleop.tick(later), vec![Message::Speed(0.2)]);
     }
 }
@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// Delay before the first reconnection, doubled after each failed one
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Delays between reconnections, doubling while they fail
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(INITIAL_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+/// How a client of the competition servers is doing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStatus {
+    pub connected: bool,
+    pub last_message: Option<Instant>,
+    /// Failed connections, and messages that could not be received or parsed
+    pub errors: u64,
+    pub last_error: Option<String>,
+    pub reconnections: u64,
+}
+
+impl ConnectionStatus {
+    /// Time since the last message, or [None] if there was none yet
+    pub fn get_last_message_age(&self, now: Instant) -> Option<Duration> {
+        self.last_message
+            .map(|message| now.saturating_duration_since(message))
+    }
+}
+
+impl Display for ConnectionStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            if self.connected {
+                "connected"
+            } else {
+                "disconnected"
+            }
+        )?;
+        match self.get_last_message_age(Instant::now()) {
+            Some(age) => write!(f, ", last message {:.1}s ago", age.as_secs_f64())?,
+            None => write!(f, ", no message yet")?,
+        }
+        write!(
+            f,
+            ", {} errors, {} reconnections",
+            self.errors, self.reconnections
+        )?;
+        if let Some(error) = &self.last_error {
+            write!(f, " (last error: {error})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared status of a client, updated by the client and read by the rest of the car
+#[derive(Debug, Clone, Default)]
+pub struct StatusHandle(Arc<Mutex<ConnectionStatus>>);
+
+impl StatusHandle {
+    fn lock(&self) -> MutexGuard<'_, ConnectionStatus> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self) -> ConnectionStatus {
+        self.lock().clone()
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.lock().connected = connected;
+    }
+
+    pub fn record_message(&self, now: Instant) {
+        self.lock().last_message = Some(now);
+    }
+
+    pub fn record_reconnection(&self) {
+        self.lock().reconnections += 1;
+    }
+
+    pub fn record_error(&self, error: &io::Error) {
+        let mut status = self.lock();
+        status.errors += 1;
+        status.last_error = Some(error.to_string());
+    }
+}
+
+/// Status of every client of the competition servers
+#[derive(Debug, Clone, Default)]
+pub struct ServerStatus {
+    pub localisation: StatusHandle,
+    pub traffic_lights: StatusHandle,
+    pub moving_obstacle: StatusHandle,
+    pub environment: StatusHandle,
+}
+
+impl ServerStatus {
+    pub fn get_clients(&self) -> [(&'static str, ConnectionStatus); 4] {
+        [
+            ("localisation", self.localisation.get()),
+            ("traffic lights", self.traffic_lights.get()),
+            ("moving obstacle", self.moving_obstacle.get()),
+            ("environment", self.environment.get()),
+        ]
+    }
+}
+
+/**
+ * Runs the client until it stops by itself, reconnecting with a growing delay whenever it fails.
+ * The delay is reset once a connection received messages.
+ */
+pub async fn supervise<F, Fut>(
+    name: &str,
+    status: StatusHandle,
+    mut backoff: Backoff,
+    mut client: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    loop {
+        let last_message = status.get().last_message;
+        let result = client().await;
+        status.set_connected(false);
+
+        match result {
+            Ok(()) => {
+                info!("The {name} client stopped");
+                return;
+            }
+            Err(e) => {
+                status.record_error(&e);
+                if status.get().last_message != last_message {
+                    backoff.reset();
+                }
+                let delay = backoff.next_delay();
+                warn!(
+                    "The {name} client failed: {e}, reconnecting in {:.1}s",
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                status.record_reconnection();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<Duration> = (0..7).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays[0], INITIAL_BACKOFF);
+        assert_eq!(delays[1], INITIAL_BACKOFF * 2);
+        assert_eq!(delays[6], MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn test_supervise_reconnects() {
+        let status = StatusHandle::default();
+        let mut attempts = 0;
+        let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(4));
+
+        let supervisor = supervise("test", status.clone(), backoff, || {
+            attempts += 1;
+            let status = status.clone();
+            let attempt = attempts;
+            async move {
+                status.set_connected(true);
+                match attempt {
+                    1 | 2 => Err(io::Error::new(io::ErrorKind::TimedOut, "no answer")),
+                    3 => {
+                        status.record_message(Instant::now());
+                        Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        });
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(supervisor);
+
+        let status = status.get();
+        assert!(!status.connected);
+        assert_eq!(status.errors, 3);
+        assert_eq!(status.reconnections, 3);
+        assert_eq!(status.last_error.as_deref(), Some("reset"));
+        assert!(status.last_message.is_some());
+    }
+}
@@ -1,14 +1,20 @@
-use crate::server::data::{TrafficLight, TrafficLightsStatus};
+use std::time::Instant;
+
+use crate::server::data::{TrafficLight, TrafficLightsStatus, TRAFFIC_LIGHT_COUNT};
+use crate::server::supervisor::{supervise, Backoff, StatusHandle};
 use crate::server::ServerData;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::Sender;
 use tracing::error;
 
+/// Where the traffic lights broadcast their colour
+pub(super) const BIND_ADDRESS: &str = "0.0.0.0:50007";
+
 async fn parse_data(socket: &UdpSocket) -> std::io::Result<TrafficLight> {
     let mut buffer = [0; 4096];
     let size = socket.recv(&mut buffer).await?;
     let traffic_light: TrafficLight = serde_json::from_slice(&buffer[..size])?;
-    if traffic_light.id == 0 || traffic_light.id > 4 {
+    if traffic_light.id == 0 || traffic_light.id > TRAFFIC_LIGHT_COUNT {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             format!("Invalid traffic light id ({})", traffic_light.id),
@@ -17,26 +23,44 @@ async fn parse_data(socket: &UdpSocket) -> std::io::Result<TrafficLight> {
     Ok(traffic_light)
 }
 
-pub async fn run_listener(sender: Sender<ServerData>) -> std::io::Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:50007").await?;
+async fn listen(sender: &Sender<ServerData>, status: &StatusHandle) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(BIND_ADDRESS).await?;
+    status.set_connected(true);
 
     let mut traffic_lights = TrafficLightsStatus::default();
 
     loop {
         match parse_data(&socket).await {
-            Ok(traffic_light) => match traffic_light.id {
-                1 => traffic_lights.0 = traffic_light.color,
-                2 => traffic_lights.1 = traffic_light.color,
-                3 => traffic_lights.2 = traffic_light.color,
-                4 => traffic_lights.3 = traffic_light.color,
-                _ => unreachable!(),
-            },
-            Err(e) => error!("Error occurred while receiving/parsing data: {}", e),
+            Ok(traffic_light) => {
+                status.record_message(Instant::now());
+                match traffic_light.id {
+                    1 => traffic_lights.0 = traffic_light.color,
+                    2 => traffic_lights.1 = traffic_light.color,
+                    3 => traffic_lights.2 = traffic_light.color,
+                    4 => traffic_lights.3 = traffic_light.color,
+                    _ => unreachable!(),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                status.record_error(&e);
+                error!("Error occurred while parsing data: {}", e);
+            }
+            Err(e) => return Err(e),
         }
 
-        sender
+        if sender
             .send(ServerData::TrafficLights(traffic_lights))
             .await
-            .unwrap();
+            .is_err()
+        {
+            return Ok(());
+        }
     }
 }
+
+pub async fn run_listener(sender: Sender<ServerData>, status: StatusHandle) {
+    supervise("traffic lights", status.clone(), Backoff::default(), || {
+        listen(&sender, &status)
+    })
+    .await;
+}
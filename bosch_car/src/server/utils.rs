@@ -1,9 +1,13 @@
+use std::future::Future;
 use std::str;
+use std::time::Duration;
 
 use rsa::{Pss, PublicKey, RsaPrivateKey, RsaPublicKey};
 use tokio::net::UdpSocket;
 
 pub const CAR_ID: &str = "69"; // TODO How would I know?
+/// Servers not answering for this long while connecting are given up on
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub fn parse_port(buffer: &[u8]) -> Option<u16> {
     str::from_utf8(buffer).ok()?.parse::<u16>().ok()
@@ -23,7 +27,7 @@ fn verify_signature(public_key: RsaPublicKey, message: &[u8], signature: &[u8])
 
 pub async fn check_authentication(
     public_key: RsaPublicKey,
-    socket: UdpSocket,
+    socket: &UdpSocket,
 ) -> std::io::Result<()> {
     // Receive response
     let mut message_buffer = [0u8; 4096];
@@ -66,3 +70,20 @@ pub async fn listen_for_port<S: AsRef<str>>(address: S) -> std::io::Result<Strin
 
     Ok(format!("{}:{}", address.ip(), port))
 }
+
+/// Socket exchanging with the server at `address` only
+pub async fn connect(address: &str) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(address).await?;
+    Ok(socket)
+}
+
+/// Fails with [std::io::ErrorKind::TimedOut] if `future` does not complete in time
+pub async fn with_timeout<T>(
+    duration: Duration,
+    future: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    tokio::time::timeout(duration, future).await.map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "The server did not answer")
+    })?
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::server::data::TRAFFIC_LIGHT_COUNT;
+use crate::track::data::{Track, TrackNode};
+
+/// What can be found at a place of the track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tag {
+    IntersectionEntry,
+    Roundabout,
+    Highway,
+    Crosswalk,
+    ParkingZone,
+    StopLine,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AnnotationsFile {
+    nodes: Vec<NodeAnnotation>,
+    edges: Vec<EdgeAnnotation>,
+    traffic_lights: Vec<TrafficLightAnnotation>,
+}
+
+/// Tags given to every node of `ids`
+#[derive(Debug, Deserialize)]
+struct NodeAnnotation {
+    ids: Vec<usize>,
+    tags: Vec<Tag>,
+}
+
+/// Tags given to every edge between consecutive nodes of `path`
+#[derive(Debug, Deserialize)]
+struct EdgeAnnotation {
+    path: Vec<usize>,
+    tags: Vec<Tag>,
+}
+
+/// Stop lines, as node ids, controlled by the traffic light with the server's `id`
+#[derive(Debug, Deserialize)]
+struct TrafficLightAnnotation {
+    id: u8,
+    nodes: Vec<usize>,
+}
+
+/// Semantic tags of the nodes and edges of a track
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Annotations {
+    nodes: HashMap<usize, Vec<Tag>>,
+    edges: HashMap<(usize, usize), Vec<Tag>>,
+    /// Traffic light controlling each stop line node
+    traffic_lights: HashMap<usize, u8>,
+}
+
+fn add_tags(current: &mut Vec<Tag>, tags: &[Tag]) {
+    for tag in tags {
+        if !current.contains(tag) {
+            current.push(*tag);
+        }
+    }
+}
+
+impl Annotations {
+    pub fn tag_node(&mut self, id: usize, tags: &[Tag]) {
+        add_tags(self.nodes.entry(id).or_default(), tags);
+    }
+
+    pub fn tag_edge(&mut self, source: usize, target: usize, tags: &[Tag]) {
+        add_tags(self.edges.entry((source, target)).or_default(), tags);
+    }
+
+    pub fn set_traffic_light(&mut self, node: usize, id: u8) {
+        self.traffic_lights.insert(node, id);
+    }
+
+    /**
+     * Reads a sidecar annotations file, checking that every tagged node and edge exists in `track`.
+     * The file looks like
+     * `{"nodes": [{"ids": [2, 4], "tags": ["stop_line"]}], "edges": [{"path": [40, 41, 42], "tags": ["highway"]}],
+     * "traffic_lights": [{"id": 1, "nodes": [2]}]}`
+     */
+    pub fn load(path: &Path, track: &Track) -> io::Result<Annotations> {
+        let file: AnnotationsFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let mut annotations = Annotations::default();
+
+        for node in file.nodes {
+            for id in node.ids {
+                if id == 0 || track.get_node_by_id(id).is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Annotated node {id} does not exist"),
+                    ));
+                }
+                annotations.tag_node(id, &node.tags);
+            }
+        }
+
+        for edge in file.edges {
+            if edge.path.len() < 2 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Annotated path {:?} needs at least two nodes", edge.path),
+                ));
+            }
+
+            for pair in edge.path.windows(2) {
+                if track.get_edge(pair[0], pair[1]).is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Annotated edge {} -> {} does not exist", pair[0], pair[1]),
+                    ));
+                }
+                annotations.tag_edge(pair[0], pair[1], &edge.tags);
+            }
+        }
+
+        for light in file.traffic_lights {
+            if !(1..=TRAFFIC_LIGHT_COUNT).contains(&light.id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid traffic light id ({})", light.id),
+                ));
+            }
+
+            for id in light.nodes {
+                if id == 0 || track.get_node_by_id(id).is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Node {id} of traffic light {} does not exist", light.id),
+                    ));
+                }
+                annotations.set_traffic_light(id, light.id);
+            }
+        }
+
+        Ok(annotations)
+    }
+}
+
+impl Track {
+    pub fn get_node_tags(&self, id: usize) -> &[Tag] {
+        self.annotations.nodes.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn get_edge_tags(&self, source: usize, target: usize) -> &[Tag] {
+        self.annotations
+            .edges
+            .get(&(source, target))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn node_has_tag(&self, id: usize, tag: Tag) -> bool {
+        self.get_node_tags(id).contains(&tag)
+    }
+
+    pub fn edge_has_tag(&self, source: usize, target: usize, tag: Tag) -> bool {
+        self.get_edge_tags(source, target).contains(&tag)
+    }
+
+    /**
+     * Index of the first node of `route`, starting from `from`, that either has the tag
+     * or is the start of an edge of the route that has it
+     */
+    pub fn find_next_tag(&self, route: &[&TrackNode], from: usize, tag: Tag) -> Option<usize> {
+        (from..route.len()).find(|&index| {
+            self.node_has_tag(route[index].id, tag)
+                || route
+                    .get(index + 1)
+                    .is_some_and(|next| self.edge_has_tag(route[index].id, next.id, tag))
+        })
+    }
+
+    /// Id of the traffic light controlling the stop line at the node, if any
+    pub fn get_traffic_light(&self, id: usize) -> Option<u8> {
+        self.annotations.traffic_lights.get(&id).copied()
+    }
+
+    /// Index of the next node of `route`, starting from `from`, with a traffic light, and the light's id
+    pub fn find_next_traffic_light(
+        &self,
+        route: &[&TrackNode],
+        from: usize,
+    ) -> Option<(usize, u8)> {
+        route
+            .iter()
+            .enumerate()
+            .skip(from)
+            .find_map(|(index, node)| Some((index, self.get_traffic_light(node.id)?)))
+    }
+
+    /// Index of the next node of `route` at which the car enters an intersection
+    pub fn find_next_intersection(&self, route: &[&TrackNode], from: usize) -> Option<usize> {
+        self.find_next_tag(route, from, Tag::IntersectionEntry)
+    }
+
+    /**
+     * Checks if the car, at the given position and heading, is driving on an edge tagged as highway
+     */
+    pub fn is_on_highway(&self, x: f32, y: f32, heading: f32) -> bool {
+        self.match_position(x, y, heading)
+            .is_some_and(|edge| self.edge_has_tag(edge.source.id, edge.target.id, Tag::Highway))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{find_path, get_test_track, PathCosts, TRACKS_DIR};
+
+    use super::*;
+
+    #[test]
+    fn test_load_annotations() {
+        let track = get_test_track();
+        let path = Path::new(TRACKS_DIR).join("test_track.annotations.json");
+        let annotations = Annotations::load(&path, track).unwrap();
+
+        assert!(annotations.nodes[&2].contains(&Tag::IntersectionEntry));
+        assert!(annotations.nodes[&2].contains(&Tag::StopLine));
+        assert!(!annotations.nodes.contains_key(&1));
+        assert_eq!(annotations.traffic_lights[&2], 1);
+    }
+
+    #[test]
+    fn test_find_next_intersection() {
+        let track = get_test_track();
+        let path = find_path(
+            track,
+            track.get_node_by_id(56).unwrap(),
+            track.get_node_by_id(3).unwrap(),
+            &PathCosts::default(),
+        )
+        .unwrap();
+
+        let index = track.find_next_intersection(&path.nodes, 0).unwrap();
+        assert_eq!(path.nodes[index].id, 2);
+        assert_eq!(track.find_next_intersection(&path.nodes, index + 1), None);
+    }
+
+    #[test]
+    fn test_is_on_highway() {
+        let mut track = Track::new(get_test_track().get_nodes().to_vec());
+        let mut annotations = Annotations::default();
+        annotations.tag_edge(57, 58, &[Tag::Highway]);
+        track.set_annotations(annotations);
+
+        let source = track.get_node_by_id(57).unwrap();
+        let target = track.get_node_by_id(58).unwrap();
+        let x = (source.get_x() + target.get_x()) / 2.0;
+        let y = (source.get_y() + target.get_y()) / 2.0;
+        let heading = source.heading_to(target);
+
+        assert!(track.is_on_highway(x, y, heading));
+        assert!(!track.is_on_highway(x, y, heading + std::f32::consts::PI));
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::server::data::ObstacleId;
+use crate::track::data::{Track, TrackNode};
+use crate::track::mission::{extend_mission_avoiding, Mission};
+use crate::track::pathfinding::{get_path_cost, PathCosts};
+
+/// How long a lane stays blocked by a static car, after which it is assumed to be gone
+const STATIC_CAR_BLOCK_DURATION: Duration = Duration::from_secs(30);
+
+/// Edges and nodes that cannot be driven through, each either forever ([None]) or until an instant
+#[derive(Debug, Default, Clone)]
+pub struct Blockages {
+    edges: HashMap<(usize, usize), Option<Instant>>,
+    nodes: HashMap<usize, Option<Instant>>,
+}
+
+fn is_active(expiry: Option<&Option<Instant>>) -> bool {
+    match expiry {
+        Some(Some(expiry)) => Instant::now() < *expiry,
+        Some(None) => true,
+        None => false,
+    }
+}
+
+impl Blockages {
+    pub fn block_edge(&mut self, source: usize, target: usize, duration: Option<Duration>) {
+        self.edges
+            .insert((source, target), duration.map(|d| Instant::now() + d));
+    }
+
+    #[allow(
+        dead_code,
+        reason = "nothing detects closed nodes yet, planning already avoids them"
+    )]
+    pub fn block_node(&mut self, id: usize, duration: Option<Duration>) {
+        self.nodes.insert(id, duration.map(|d| Instant::now() + d));
+    }
+
+    pub fn unblock_edge(&mut self, source: usize, target: usize) {
+        self.edges.remove(&(source, target));
+    }
+
+    pub fn unblock_node(&mut self, id: usize) {
+        self.nodes.remove(&id);
+    }
+
+    pub fn is_edge_blocked(&self, source: usize, target: usize) -> bool {
+        is_active(self.edges.get(&(source, target)))
+    }
+
+    pub fn is_node_blocked(&self, id: usize) -> bool {
+        is_active(self.nodes.get(&id))
+    }
+
+    /**
+     * Checks if any node after the first one, or any edge between them, is blocked
+     */
+    pub fn is_path_blocked(&self, nodes: &[&TrackNode]) -> bool {
+        nodes.windows(2).any(|pair| {
+            self.is_edge_blocked(pair[0].id, pair[1].id) || self.is_node_blocked(pair[1].id)
+        })
+    }
+
+    /// Lifts every blockage on the path, e.g. when there is no way around them
+    pub fn unblock_path(&mut self, nodes: &[&TrackNode]) {
+        for pair in nodes.windows(2) {
+            self.unblock_edge(pair[0].id, pair[1].id);
+            self.unblock_node(pair[1].id);
+        }
+    }
+
+    /// Forgets about blockages whose timeout has passed
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let is_valid = |expiry: &mut Option<Instant>| expiry.is_none_or(|expiry| now < expiry);
+
+        self.edges.retain(|_, expiry| is_valid(expiry));
+        self.nodes.retain(|_, expiry| is_valid(expiry));
+    }
+
+    /**
+     * Blocks the lane of an obstacle found at the given track coordinates by a car driving with
+     * `heading`, if that kind of obstacle blocks the road. Returns the blocked edge as (source, target).
+     */
+    pub fn block_obstacle(
+        &mut self,
+        track: &Track,
+        id: ObstacleId,
+        x: f32,
+        y: f32,
+        heading: f32,
+    ) -> Option<(usize, usize)> {
+        let duration = match id {
+            ObstacleId::Roadblock => None,
+            ObstacleId::StaticCarOnRoad => Some(STATIC_CAR_BLOCK_DURATION),
+            _ => return None,
+        };
+
+        let projection = track
+            .match_position(x, y, heading)
+            .or_else(|| track.find_closest_edge(x, y))?;
+        let (source, target) = (projection.source.id, projection.target.id);
+        if !self.is_edge_blocked(source, target) {
+            info!("Blocked edge {source} -> {target} because of {id:?} at ({x}, {y})");
+        }
+        self.block_edge(source, target, duration);
+
+        Some((source, target))
+    }
+}
+
+/**
+ * Plans the rest of `mission` again, the car being at `mission.nodes[current_index]`.
+ * The part that was already driven and the remaining waypoints are kept, while only the
+ * segments between waypoints that became blocked (or that start from a different direction
+ * because of an earlier change) are searched again.
+ */
+pub fn replan_mission<'a>(
+    track: &'a Track,
+    mission: &Mission<'a>,
+    current_index: usize,
+    blockages: &Blockages,
+    costs: &PathCosts,
+) -> Result<Mission<'a>, String> {
+    if current_index >= mission.nodes.len() {
+        return Err(format!(
+            "Current index {current_index} is outside of the mission"
+        ));
+    }
+
+    let driven_nodes = &mission.nodes[..=current_index];
+    let mut new_mission = Mission {
+        nodes: driven_nodes.to_vec(),
+        cost: get_path_cost(None, driven_nodes, costs).unwrap_or_default(),
+        waypoint_indices: mission
+            .waypoint_indices
+            .iter()
+            .copied()
+            .filter(|&index| index <= current_index)
+            .collect(),
+    };
+
+    let mut segment_start = current_index;
+
+    for &waypoint_index in mission
+        .waypoint_indices
+        .iter()
+        .filter(|&&index| index > current_index)
+    {
+        let old_segment = &mission.nodes[segment_start..=waypoint_index];
+        let last_index = new_mission.nodes.len() - 1;
+
+        let is_same_start = new_mission.nodes[last_index] == old_segment[0]
+            && new_mission.get_previous_node(last_index)
+                == mission.get_previous_node(segment_start);
+
+        let reusable_cost = if is_same_start && !blockages.is_path_blocked(old_segment) {
+            get_path_cost(mission.get_previous_node(segment_start), old_segment, costs)
+        } else {
+            None
+        };
+
+        match reusable_cost {
+            Some(cost) => {
+                new_mission.nodes.extend_from_slice(&old_segment[1..]);
+                new_mission.cost += cost;
+                new_mission
+                    .waypoint_indices
+                    .push(new_mission.nodes.len() - 1);
+            }
+            None => extend_mission_avoiding(
+                track,
+                &mut new_mission,
+                mission.nodes[waypoint_index],
+                blockages,
+                costs,
+            )?,
+        }
+
+        segment_start = waypoint_index;
+    }
+
+    Ok(new_mission)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{get_test_track, plan_mission, Waypoint};
+
+    use super::*;
+
+    #[test]
+    fn test_blockage_expiry() {
+        let mut blockages = Blockages::default();
+        blockages.block_edge(1, 2, None);
+        blockages.block_node(3, Some(Duration::ZERO));
+
+        assert!(blockages.is_edge_blocked(1, 2));
+        assert!(!blockages.is_edge_blocked(2, 1));
+        assert!(!blockages.is_node_blocked(3));
+
+        blockages.remove_expired();
+        assert!(blockages.nodes.is_empty());
+
+        blockages.unblock_edge(1, 2);
+        assert!(!blockages.is_edge_blocked(1, 2));
+    }
+
+    #[test]
+    fn test_replan_around_blocked_edge() {
+        let track = get_test_track();
+        let costs = PathCosts::default();
+        let waypoints = [Waypoint::Node(24), Waypoint::Node(60), Waypoint::Node(90)];
+        let mission = plan_mission(track, &waypoints, &costs).unwrap();
+
+        // Block an edge inside an intersection on the way to the last waypoint,
+        // while the car is driving towards the second one
+        let current_index = 1;
+        let blocked_index = mission.nodes.iter().position(|node| node.id == 12).unwrap();
+        let mut blockages = Blockages::default();
+        blockages.block_edge(
+            mission.nodes[blocked_index].id,
+            mission.nodes[blocked_index + 1].id,
+            None,
+        );
+
+        let new_mission =
+            replan_mission(track, &mission, current_index, &blockages, &costs).unwrap();
+
+        assert_eq!(
+            new_mission.nodes[..=current_index],
+            mission.nodes[..=current_index]
+        );
+        assert!(!blockages.is_path_blocked(&new_mission.nodes[current_index..]));
+        assert_eq!(new_mission.waypoint_indices.len(), waypoints.len());
+        for (index, waypoint) in new_mission.waypoint_indices.iter().zip(waypoints) {
+            assert_eq!(Ok(new_mission.nodes[*index]), waypoint.resolve(track));
+        }
+        assert!(new_mission.cost > mission.cost);
+
+        // Without any blockages nothing changes
+        let same_mission = replan_mission(
+            track,
+            &mission,
+            current_index,
+            &Blockages::default(),
+            &costs,
+        )
+        .unwrap();
+        assert_eq!(same_mission.nodes, mission.nodes);
+        assert!((same_mission.cost - mission.cost).abs() < 1e-2);
+    }
+}
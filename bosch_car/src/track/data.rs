@@ -2,6 +2,9 @@ use std::cmp::Ordering;
 
 use ordered_float::OrderedFloat;
 
+use crate::track::annotations::Annotations;
+use crate::track::spatial::SpatialGrid;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TrackEdge {
     pub target: usize,
@@ -35,23 +38,30 @@ impl TrackNode {
     pub fn get_y(&self) -> f32 {
         self.y.into_inner()
     }
+
+    pub fn distance_to(&self, other: &TrackNode) -> f32 {
+        (self.x.0 - other.x.0).hypot(self.y.0 - other.y.0)
+    }
+
+    /**
+     * Heading of the straight line from this node to `other`, in radians
+     */
+    pub fn heading_to(&self, other: &TrackNode) -> f32 {
+        (other.y.0 - self.y.0).atan2(other.x.0 - self.x.0)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct State<'a> {
     pub cost: OrdFloat,
     pub node: &'a TrackNode,
+    /// The node we arrived from, needed to know the heading of the car
+    pub previous: Option<&'a TrackNode>,
 }
 
 impl<'a> PartialOrd for State<'a> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let ordering = other.cost.partial_cmp(&self.cost);
-
-        if ordering == Some(Ordering::Equal) {
-            self.node.partial_cmp(other.node)
-        } else {
-            ordering
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -66,15 +76,38 @@ impl<'a> Ord for State<'a> {
             .cost
             .cmp(&self.cost)
             .then_with(|| self.node.cmp(other.node))
+            .then_with(|| self.previous.cmp(&other.previous))
     }
 }
 
-pub struct Track(pub Vec<TrackNode>);
+pub struct Track {
+    nodes: Vec<TrackNode>,
+    pub(super) grid: SpatialGrid,
+    pub(super) annotations: Annotations,
+}
 
 impl Track {
+    pub fn new(nodes: Vec<TrackNode>) -> Track {
+        let grid = SpatialGrid::new(&nodes);
+        Track {
+            nodes,
+            grid,
+            annotations: Annotations::default(),
+        }
+    }
+
+    pub fn set_annotations(&mut self, annotations: Annotations) {
+        self.annotations = annotations;
+    }
+
+    /// All nodes, indexed by their id. The first one is a placeholder since ids start from 1.
+    pub fn get_nodes(&self) -> &[TrackNode] {
+        &self.nodes
+    }
+
     #[inline(always)]
     pub fn get_node_by_id(&self, id: usize) -> Option<&TrackNode> {
-        self.0.get(id)
+        self.nodes.get(id)
     }
 
     pub fn get_edges(&self, source: usize) -> Option<&Vec<TrackEdge>> {
@@ -82,15 +115,7 @@ impl Track {
     }
 
     pub fn get_edge(&self, source: usize, target: usize) -> Option<&TrackEdge> {
-        self.get_edges(source).and_then(|edges| edges.get(target))
-    }
-
-    pub fn find_closest_node(&self, x: f32, y: f32) -> Option<&TrackNode> {
-        self.0.iter().min_by(|a, b| {
-            (x - a.x.0)
-                .hypot(y - a.y.0)
-                .partial_cmp(&(x - b.x.0).hypot(y - b.y.0))
-                .unwrap()
-        })
+        self.get_edges(source)
+            .and_then(|edges| edges.iter().find(|edge| edge.target == target))
     }
 }
@@ -0,0 +1,364 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::{json, Value};
+use shared::math::CarPosition;
+
+use crate::server::data::EnvironmentalObstacle;
+use crate::track::data::{Track, TrackNode};
+
+/// Empty space around the map in the SVG, in centimeters
+const SVG_MARGIN: f32 = 30.0;
+const SVG_NODE_RADIUS: f32 = 3.0;
+/// Length of the triangle drawn for the car, in centimeters
+const SVG_CAR_LENGTH: f32 = 25.0;
+
+/**
+ * What is drawn on top of the map when exporting it.
+ * The car and obstacles are in meters, in the track's frame whose y axis points up, like the
+ * positions of the [Brain](crate::brain::Brain). Positions from the server, whose y axis points
+ * down, have to be flipped with the height of the track first.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct MapOverlay<'a> {
+    pub route: Vec<&'a TrackNode>,
+    pub car: Option<CarPosition>,
+    pub obstacles: Vec<EnvironmentalObstacle>,
+}
+
+/// Track coordinates are in centimeters, in the same frame otherwise
+fn meters_to_track(x: f64, y: f64) -> (f32, f32) {
+    (x as f32 * 100.0, y as f32 * 100.0)
+}
+
+/// Converts track coordinates to SVG ones, whose y axis points down
+struct SvgFrame {
+    min_x: f32,
+    max_y: f32,
+}
+
+impl SvgFrame {
+    fn convert(&self, x: f32, y: f32) -> (f32, f32) {
+        (x - self.min_x + SVG_MARGIN, self.max_y - y + SVG_MARGIN)
+    }
+}
+
+impl Track {
+    /// Nodes with an actual position, without the placeholder
+    fn get_drawn_nodes(&self) -> &[TrackNode] {
+        &self.get_nodes()[1..]
+    }
+
+    /**
+     * Draws the graph and the overlay as an SVG image, in centimeters.
+     * Solid edges are drawn as full lines and dotted edges as dashed lines, with an arrow towards their target.
+     */
+    pub fn to_svg(&self, overlay: &MapOverlay) -> String {
+        let car = overlay
+            .car
+            .as_ref()
+            .map(|car| meters_to_track(car.x, car.y));
+        let obstacles = overlay
+            .obstacles
+            .iter()
+            .map(|obstacle| meters_to_track(obstacle.x as f64, obstacle.y as f64));
+        let points: Vec<(f32, f32)> = self
+            .get_drawn_nodes()
+            .iter()
+            .map(|node| (node.get_x(), node.get_y()))
+            .chain(car)
+            .chain(obstacles)
+            .collect();
+
+        let min_x = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+        let max_x = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+        let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+        let (min_x, max_x, min_y, max_y) = if points.is_empty() {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (min_x, max_x, min_y, max_y)
+        };
+
+        let frame = SvgFrame { min_x, max_y };
+        let width = max_x - min_x + 2.0 * SVG_MARGIN;
+        let height = max_y - min_y + 2.0 * SVG_MARGIN;
+
+        // Writing to a String cannot fail
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width:.0}" height="{height:.0}" viewBox="0 0 {width:.1} {height:.1}">"#
+        );
+        let _ = writeln!(
+            svg,
+            r##"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="4" markerHeight="4" orient="auto"><path d="M 0 0 L 10 5 L 0 10 z" fill="#555"/></marker></defs>"##
+        );
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+
+        let _ = writeln!(svg, r##"<g id="edges" stroke="#555" stroke-width="1.5">"##);
+        for node in self.get_drawn_nodes() {
+            for edge in &node.edges {
+                let Some(target) = self.get_node_by_id(edge.target) else {
+                    continue;
+                };
+                let (x1, y1) = frame.convert(node.get_x(), node.get_y());
+                let (x2, y2) = frame.convert(target.get_x(), target.get_y());
+                let dash = if edge.dotted {
+                    r#" stroke-dasharray="4 3""#
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    svg,
+                    r#"<line x1="{x1:.1}" y1="{y1:.1}" x2="{x2:.1}" y2="{y2:.1}"{dash} marker-end="url(#arrow)"/>"#
+                );
+            }
+        }
+        let _ = writeln!(svg, "</g>");
+
+        if overlay.route.len() > 1 {
+            let points: Vec<String> = overlay
+                .route
+                .iter()
+                .map(|node| {
+                    let (x, y) = frame.convert(node.get_x(), node.get_y());
+                    format!("{x:.1},{y:.1}")
+                })
+                .collect();
+            let _ = writeln!(
+                svg,
+                r#"<polyline id="route" points="{}" fill="none" stroke="red" stroke-width="4" stroke-opacity="0.6"/>"#,
+                points.join(" ")
+            );
+        }
+
+        let _ = writeln!(
+            svg,
+            r#"<g id="nodes" font-size="6" font-family="sans-serif">"#
+        );
+        for node in self.get_drawn_nodes() {
+            let (x, y) = frame.convert(node.get_x(), node.get_y());
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{x:.1}" cy="{y:.1}" r="{SVG_NODE_RADIUS}" fill="steelblue"/><text x="{:.1}" y="{:.1}">{}</text>"#,
+                x + SVG_NODE_RADIUS,
+                y - SVG_NODE_RADIUS,
+                node.id
+            );
+        }
+        let _ = writeln!(svg, "</g>");
+
+        for obstacle in &overlay.obstacles {
+            let (x, y) = meters_to_track(obstacle.x as f64, obstacle.y as f64);
+            let (x, y) = frame.convert(x, y);
+            let _ = writeln!(
+                svg,
+                r#"<g class="obstacle"><rect x="{:.1}" y="{:.1}" width="10" height="10" fill="orange"/><text x="{:.1}" y="{:.1}" font-size="6">{:?}</text></g>"#,
+                x - 5.0,
+                y - 5.0,
+                x + 6.0,
+                y - 6.0,
+                obstacle.id
+            );
+        }
+
+        if let Some(car) = &overlay.car {
+            let (x, y) = meters_to_track(car.x, car.y);
+            let angle = car.angle as f32;
+            // Triangle pointing towards the heading of the car
+            let corners = [
+                (SVG_CAR_LENGTH / 2.0, 0.0),
+                (-SVG_CAR_LENGTH / 2.0, SVG_CAR_LENGTH / 3.0),
+                (-SVG_CAR_LENGTH / 2.0, -SVG_CAR_LENGTH / 3.0),
+            ];
+            let points: Vec<String> = corners
+                .iter()
+                .map(|(forward, left)| {
+                    let (x, y) = frame.convert(
+                        x + forward * angle.cos() - left * angle.sin(),
+                        y + forward * angle.sin() + left * angle.cos(),
+                    );
+                    format!("{x:.1},{y:.1}")
+                })
+                .collect();
+            let _ = writeln!(
+                svg,
+                r#"<polygon id="car" points="{}" fill="green"/>"#,
+                points.join(" ")
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /**
+     * Describes the graph and the overlay as a GeoJSON feature collection. Coordinates are
+     * the track's own (centimeters) rather than longitudes and latitudes, and every feature has
+     * a `kind` property (`node`, `edge`, `route`, `car` or `obstacle`) to style it.
+     */
+    pub fn to_geojson(&self, overlay: &MapOverlay) -> Value {
+        let mut features = Vec::new();
+
+        for node in self.get_drawn_nodes() {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [node.get_x(), node.get_y()] },
+                "properties": {
+                    "kind": "node",
+                    "id": node.id,
+                    "tags": self.get_node_tags(node.id),
+                },
+            }));
+        }
+
+        for node in self.get_drawn_nodes() {
+            for edge in &node.edges {
+                let Some(target) = self.get_node_by_id(edge.target) else {
+                    continue;
+                };
+                features.push(json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[node.get_x(), node.get_y()], [target.get_x(), target.get_y()]],
+                    },
+                    "properties": {
+                        "kind": "edge",
+                        "source": node.id,
+                        "target": edge.target,
+                        "dotted": edge.dotted,
+                        "tags": self.get_edge_tags(node.id, edge.target),
+                    },
+                }));
+            }
+        }
+
+        if overlay.route.len() > 1 {
+            let coordinates: Vec<[f32; 2]> = overlay
+                .route
+                .iter()
+                .map(|node| [node.get_x(), node.get_y()])
+                .collect();
+            let ids: Vec<usize> = overlay.route.iter().map(|node| node.id).collect();
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": coordinates },
+                "properties": { "kind": "route", "nodes": ids },
+            }));
+        }
+
+        if let Some(car) = &overlay.car {
+            let (x, y) = meters_to_track(car.x, car.y);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [x, y] },
+                "properties": { "kind": "car", "heading": car.angle },
+            }));
+        }
+
+        for obstacle in &overlay.obstacles {
+            let (x, y) = meters_to_track(obstacle.x as f64, obstacle.y as f64);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [x, y] },
+                "properties": {
+                    "kind": "obstacle",
+                    "obstacle": obstacle.id,
+                },
+            }));
+        }
+
+        json!({ "type": "FeatureCollection", "features": features })
+    }
+}
+
+/**
+ * Writes the map and the overlay to `path`, as SVG if it ends with `.svg` and as GeoJSON otherwise
+ */
+pub fn export_map(track: &Track, overlay: &MapOverlay, path: &Path) -> io::Result<()> {
+    let contents = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("svg") => track.to_svg(overlay),
+        _ => serde_json::to_string_pretty(&track.to_geojson(overlay))?,
+    };
+
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::data::ObstacleId;
+    use crate::track::{find_path, get_test_track, PathCosts};
+
+    use super::*;
+
+    #[test]
+    fn test_export_map() {
+        let track = get_test_track();
+        let path = find_path(
+            track,
+            track.get_node_by_id(24).unwrap(),
+            track.get_node_by_id(60).unwrap(),
+            &PathCosts::default(),
+        )
+        .unwrap();
+        let overlay = MapOverlay {
+            route: path.nodes.clone(),
+            car: Some(CarPosition::new(1.0, 1.0, 0.5)),
+            obstacles: vec![EnvironmentalObstacle {
+                id: ObstacleId::Roadblock,
+                x: 2.0,
+                y: 1.0,
+            }],
+        };
+
+        let nodes = track.get_drawn_nodes();
+        let edge_count: usize = nodes.iter().map(|node| node.edges.len()).sum();
+        let dotted_count = nodes
+            .iter()
+            .flat_map(|node| &node.edges)
+            .filter(|edge| edge.dotted)
+            .count();
+
+        let svg = track.to_svg(&overlay);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<line ").count(), edge_count);
+        assert_eq!(svg.matches("stroke-dasharray").count(), dotted_count);
+        assert_eq!(svg.matches("<circle ").count(), nodes.len());
+        assert!(svg.contains(r#"id="route""#) && svg.contains(r#"id="car""#));
+        assert!(svg.contains("Roadblock"));
+
+        let geojson = track.to_geojson(&overlay);
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), nodes.len() + edge_count + 3);
+        let route = features
+            .iter()
+            .find(|feature| feature["properties"]["kind"] == "route")
+            .unwrap();
+        assert_eq!(
+            route["geometry"]["coordinates"].as_array().unwrap().len(),
+            path.nodes.len()
+        );
+        let obstacle = features
+            .iter()
+            .find(|feature| feature["properties"]["kind"] == "obstacle")
+            .unwrap();
+        assert_eq!(
+            obstacle["properties"]["obstacle"],
+            ObstacleId::Roadblock as u8
+        );
+        let tagged_node = features
+            .iter()
+            .find(|feature| {
+                feature["properties"]["kind"] == "node" && feature["properties"]["id"] == 2
+            })
+            .unwrap();
+        assert_eq!(
+            tagged_node["properties"]["tags"],
+            json!(["intersection_entry", "stop_line"])
+        );
+    }
+}
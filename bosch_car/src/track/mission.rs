@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::track::blocking::Blockages;
+use crate::track::data::{Track, TrackNode};
+use crate::track::pathfinding::{find_path_avoiding, find_path_from, PathCosts};
+
+/// Visiting every order is only feasible for a handful of waypoints
+const MAX_FREE_ORDER_WAYPOINTS: usize = 8;
+
+/// A point the car has to go through, either a node id or coordinates on the track
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Waypoint {
+    Node(usize),
+    Coords(f32, f32),
+}
+
+impl Waypoint {
+    pub fn resolve<'a>(&self, track: &'a Track) -> Result<&'a TrackNode, String> {
+        match *self {
+            Waypoint::Node(id) => track
+                .get_node_by_id(id)
+                .filter(|node| node.id != 0)
+                .ok_or_else(|| format!("Waypoint node {id} does not exist")),
+            Waypoint::Coords(x, y) => track
+                .find_closest_node(x, y)
+                .ok_or_else(|| format!("Didn't find a node close to waypoint ({x}, {y})")),
+        }
+    }
+}
+
+/// A route through the track visiting multiple waypoints
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mission<'a> {
+    pub nodes: Vec<&'a TrackNode>,
+    pub cost: f32,
+    /// Index in `nodes` at which each waypoint is reached, in visiting order
+    pub waypoint_indices: Vec<usize>,
+}
+
+impl<'a> Mission<'a> {
+    /// The node the car arrives from when reaching `nodes[index]`
+    pub fn get_previous_node(&self, index: usize) -> Option<&'a TrackNode> {
+        index.checked_sub(1).map(|index| self.nodes[index])
+    }
+}
+
+/**
+ * Chains the paths between consecutive waypoints into one route. Each path continues
+ * in the direction the previous one arrived from, so the car never has to U-turn at a waypoint.
+ */
+pub fn plan_mission<'a>(
+    track: &'a Track,
+    waypoints: &[Waypoint],
+    costs: &PathCosts,
+) -> Result<Mission<'a>, String> {
+    let nodes = waypoints
+        .iter()
+        .map(|waypoint| waypoint.resolve(track))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (first_node, next_nodes) = nodes
+        .split_first()
+        .ok_or_else(|| "A mission needs at least one waypoint".to_string())?;
+
+    let mut mission = Mission {
+        nodes: vec![*first_node],
+        cost: 0.0,
+        waypoint_indices: vec![0],
+    };
+
+    for next_node in next_nodes {
+        extend_mission_avoiding(track, &mut mission, next_node, &Blockages::default(), costs)?;
+    }
+
+    Ok(mission)
+}
+
+/// Appends the path from the last node of the mission to `next_node` as a new waypoint
+pub(super) fn extend_mission_avoiding<'a>(
+    track: &'a Track,
+    mission: &mut Mission<'a>,
+    next_node: &'a TrackNode,
+    blockages: &Blockages,
+    costs: &PathCosts,
+) -> Result<(), String> {
+    let last_index = mission.nodes.len() - 1;
+    let last_node = mission.nodes[last_index];
+
+    let path = find_path_avoiding(
+        track,
+        blockages,
+        mission.get_previous_node(last_index),
+        last_node,
+        next_node,
+        costs,
+    )
+    .map_err(|e| format!("{e} from node {} to node {}", last_node.id, next_node.id))?;
+
+    mission.nodes.extend_from_slice(&path.nodes[1..]);
+    mission.cost += path.cost;
+    mission.waypoint_indices.push(mission.nodes.len() - 1);
+
+    Ok(())
+}
+
+/// Cost of a path and the node it arrives from, keyed by (previous node, start node, end node)
+type SegmentCache = HashMap<(Option<usize>, usize, usize), Option<(f32, Option<usize>)>>;
+
+struct OrderSearch<'t, 'c> {
+    track: &'t Track,
+    costs: &'c PathCosts,
+    waypoints: Vec<&'t TrackNode>,
+    end: Option<&'t TrackNode>,
+    cache: SegmentCache,
+    best: Option<(f32, Vec<usize>)>,
+}
+
+impl<'t, 'c> OrderSearch<'t, 'c> {
+    fn get_segment(
+        &mut self,
+        previous: Option<usize>,
+        start: usize,
+        end: usize,
+    ) -> Option<(f32, Option<usize>)> {
+        let track = self.track;
+        let costs = self.costs;
+
+        *self.cache.entry((previous, start, end)).or_insert_with(|| {
+            let previous = previous.and_then(|id| track.get_node_by_id(id));
+            let start = track.get_node_by_id(start)?;
+            let end = track.get_node_by_id(end)?;
+
+            let path = find_path_from(track, previous, start, end, costs).ok()?;
+            let arrived_from = match path.nodes.len() {
+                0 | 1 => previous.map(|node| node.id),
+                len => Some(path.nodes[len - 2].id),
+            };
+
+            Some((path.cost, arrived_from))
+        })
+    }
+
+    fn search(
+        &mut self,
+        previous: Option<usize>,
+        current: usize,
+        cost: f32,
+        order: &mut Vec<usize>,
+    ) {
+        if self
+            .best
+            .as_ref()
+            .is_some_and(|(best_cost, _)| cost >= *best_cost)
+        {
+            return;
+        }
+
+        if order.len() == self.waypoints.len() {
+            let total_cost = match self.end {
+                Some(end) => match self.get_segment(previous, current, end.id) {
+                    Some((segment_cost, _)) => cost + segment_cost,
+                    None => return,
+                },
+                None => cost,
+            };
+
+            if self
+                .best
+                .as_ref()
+                .is_some_and(|(best_cost, _)| total_cost >= *best_cost)
+            {
+                return;
+            }
+
+            self.best = Some((total_cost, order.clone()));
+            return;
+        }
+
+        for index in 0..self.waypoints.len() {
+            if order.contains(&index) {
+                continue;
+            }
+
+            let next = self.waypoints[index].id;
+            if let Some((segment_cost, arrived_from)) = self.get_segment(previous, current, next) {
+                order.push(index);
+                self.search(arrived_from, next, cost + segment_cost, order);
+                order.pop();
+            }
+        }
+    }
+}
+
+/**
+ * Plans a mission from `start` through all `waypoints` in the cheapest order, optionally finishing at `end`.
+ * Returns the order in which the waypoints are visited, as indices in `waypoints`, together with the mission.
+ */
+pub fn plan_mission_any_order<'a>(
+    track: &'a Track,
+    start: Waypoint,
+    waypoints: &[Waypoint],
+    end: Option<Waypoint>,
+    costs: &PathCosts,
+) -> Result<(Vec<usize>, Mission<'a>), String> {
+    if waypoints.len() > MAX_FREE_ORDER_WAYPOINTS {
+        return Err(format!(
+            "Cannot choose the order of more than {MAX_FREE_ORDER_WAYPOINTS} waypoints"
+        ));
+    }
+
+    let start_node = start.resolve(track)?;
+    let mut search = OrderSearch {
+        track,
+        costs,
+        waypoints: waypoints
+            .iter()
+            .map(|waypoint| waypoint.resolve(track))
+            .collect::<Result<_, _>>()?,
+        end: end.map(|end| end.resolve(track)).transpose()?,
+        cache: HashMap::new(),
+        best: None,
+    };
+
+    search.search(None, start_node.id, 0.0, &mut Vec::new());
+
+    let (_, order) = search
+        .best
+        .ok_or_else(|| "No order of the waypoints can be driven".to_string())?;
+
+    let ordered_waypoints: Vec<_> = std::iter::once(start)
+        .chain(order.iter().map(|&index| waypoints[index]))
+        .chain(end)
+        .collect();
+
+    Ok((order, plan_mission(track, &ordered_waypoints, costs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::get_test_track;
+
+    use super::*;
+
+    #[test]
+    fn test_plan_mission() {
+        let track = get_test_track();
+        let costs = PathCosts::default();
+        let waypoints = [Waypoint::Node(24), Waypoint::Node(60), Waypoint::Node(90)];
+
+        let mission = plan_mission(track, &waypoints, &costs).unwrap();
+
+        assert_eq!(mission.waypoint_indices.len(), 3);
+        for (index, waypoint) in mission.waypoint_indices.iter().zip(waypoints) {
+            assert_eq!(Ok(mission.nodes[*index]), waypoint.resolve(track));
+        }
+
+        // Every step has to follow an edge without turning back
+        for (i, nodes) in mission.nodes.windows(2).enumerate() {
+            let edge = track
+                .get_edge(nodes[0].id, nodes[1].id)
+                .expect("mission uses an edge that does not exist");
+            assert!(costs
+                .get_edge_cost(mission.get_previous_node(i), nodes[0], edge, nodes[1])
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn test_plan_mission_any_order() {
+        let track = get_test_track();
+        let costs = PathCosts::default();
+        let start = Waypoint::Node(24);
+        let waypoints = [Waypoint::Node(90), Waypoint::Node(60), Waypoint::Node(50)];
+        let end = Some(Waypoint::Node(2));
+
+        let (order, mission) =
+            plan_mission_any_order(track, start, &waypoints, end, &costs).unwrap();
+
+        assert_eq!(mission.waypoint_indices.len(), 5);
+
+        let permutations = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        for permutation in permutations {
+            let ordered: Vec<_> = std::iter::once(start)
+                .chain(permutation.iter().map(|&index| waypoints[index]))
+                .chain(end)
+                .collect();
+
+            if let Ok(other) = plan_mission(track, &ordered, &costs) {
+                assert!(
+                    mission.cost <= other.cost + 1e-2,
+                    "order {order:?} is worse than {permutation:?}"
+                );
+            }
+        }
+    }
+}
@@ -1,114 +1,175 @@
-use std::collections::{BinaryHeap, HashMap};
-use std::mem::MaybeUninit;
-use std::sync::Once;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use tracing::info;
 
+pub use self::annotations::*;
+pub use self::blocking::{replan_mission, Blockages};
 pub use self::data::*;
-
+pub use self::export::*;
+pub use self::mission::*;
+pub use self::pathfinding::*;
+pub use self::spatial::project_on_segment;
+pub use self::trajectory::*;
+
+mod annotations;
+mod blocking;
 mod data;
+mod export;
+mod mission;
 mod parsing;
-
-pub fn get_track() -> &'static Track {
-    static mut SINGLETON: MaybeUninit<Track> = MaybeUninit::uninit();
-    static ONCE: Once = Once::new();
-
-    unsafe {
-        ONCE.call_once(|| {
-            SINGLETON.write(parsing::parse_track("res/tracks/test_track.json", 6.0).unwrap());
-        });
-
-        SINGLETON.assume_init_ref()
-    }
+mod pathfinding;
+mod spatial;
+mod trajectory;
+
+/// Directory in which maps can be referenced by name, found from the sources so that it does not
+/// depend on where the car is started from
+pub const TRACKS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../res/tracks");
+
+/// Which map to load and its dimensions
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrackConfig {
+    /// Path to a `.graphml` or `.json` map, or the name of a map in [TRACKS_DIR] (e.g. `test_track`)
+    pub map: String,
+    /// Height of the map in meters, needed to flip the y axis
+    pub height: f32,
+    /// Semantic annotations of the map, by default `<map>.annotations.json` next to it if it exists
+    pub annotations: Option<String>,
 }
 
-pub fn find_path_coords(
-    track: &Track,
-    start_coord: (f32, f32),
-    end_coord: (f32, f32),
-) -> Result<Vec<&TrackNode>, String> {
-    let start_node = track
-        .find_closest_node(start_coord.0, start_coord.1)
-        .ok_or_else(|| "Didn't find start node".to_string())?;
-    let end_node = track
-        .find_closest_node(end_coord.0, end_coord.1)
-        .ok_or_else(|| "Didn't find end node".to_string())?;
-
-    find_path(track, start_node, end_node)
+impl Default for TrackConfig {
+    fn default() -> Self {
+        Self {
+            map: "test_track".to_string(),
+            height: 6.0,
+            annotations: None,
+        }
+    }
 }
 
-pub fn find_path<'a>(
-    track: &'a Track,
-    start_node: &'a TrackNode,
-    end_node: &'a TrackNode,
-) -> Result<Vec<&'a TrackNode>, String> {
-    let mut prio_queue = BinaryHeap::new();
-
-    prio_queue.push(State {
-        cost: OrderedFloat(0.0),
-        node: start_node,
-    });
-
-    let mut came_from = HashMap::new();
-    came_from.insert(start_node, track.0.first().unwrap());
-
-    let mut cost_so_far = HashMap::new();
-    cost_so_far.insert(start_node, OrderedFloat(0.0));
-
-    while let Some(State { cost: _cost, node }) = prio_queue.pop() {
-        if node == end_node {
-            break;
+impl TrackConfig {
+    /**
+     * Resolves the map to a file, preferring the official GraphML over the JSON version
+     * when only the name of the map is given.
+     */
+    pub fn get_path(&self) -> PathBuf {
+        let path = Path::new(&self.map);
+        if path.extension().is_some() || path.components().count() > 1 {
+            return path.to_path_buf();
         }
 
-        for edge in &node.edges {
-            let new_cost = *cost_so_far
-                .get(node)
-                .expect("cost_so_far does not contain node");
-
-            let next_node = match track.get_node_by_id(edge.target) {
-                Some(node) => node,
-                None => panic!("track does not contain node with id {}", edge.target),
-            };
-
-            let next_node_cost = cost_so_far.get(next_node);
-
-            if next_node_cost.is_none() || new_cost < *next_node_cost.unwrap() {
-                cost_so_far.insert(next_node, new_cost);
-
-                let priority = new_cost + fast_euclidean_distance(next_node, end_node);
-
-                prio_queue.push(State {
-                    cost: priority,
-                    node: next_node,
-                });
-                came_from.insert(next_node, node);
-            }
+        let graphml = Path::new(TRACKS_DIR).join(format!("{}.graphml", self.map));
+        if graphml.exists() {
+            graphml
+        } else {
+            Path::new(TRACKS_DIR).join(format!("{}.json", self.map))
         }
     }
 
-    if !came_from.contains_key(end_node) {
-        return Err("No path found".to_string());
+    /// The annotations file to load, if any
+    pub fn get_annotations_path(&self) -> Option<PathBuf> {
+        if let Some(annotations) = &self.annotations {
+            return Some(PathBuf::from(annotations));
+        }
+
+        let path = self.get_path().with_extension("annotations.json");
+        path.exists().then_some(path)
     }
+}
 
-    let mut path = Vec::new();
-    let mut current_node = end_node;
+impl Track {
+    pub fn load(config: &TrackConfig) -> io::Result<Track> {
+        let path = config.get_path();
+        let path_str = path.to_string_lossy();
+
+        let mut track = parsing::parse_track(&path_str, config.height).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to load track {path_str}: {e}"))
+        })?;
+
+        if let Some(annotations_path) = config.get_annotations_path() {
+            let annotations = Annotations::load(&annotations_path, &track).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Failed to load annotations {}: {e}",
+                        annotations_path.display()
+                    ),
+                )
+            })?;
+            track.set_annotations(annotations);
+        }
 
-    while current_node != start_node {
-        path.push(current_node);
-        current_node = match came_from.get(current_node) {
-            Some(node) => node,
-            _ => panic!("came_from does not contain node"),
-        };
+        Ok(track)
     }
+}
 
-    path.push(start_node);
-    path.reverse();
+static TRACK: OnceLock<Track> = OnceLock::new();
+
+/**
+ * Loads the track that will be returned by [get_track], this should be called once at startup.
+ */
+pub fn init_track(config: &TrackConfig) -> io::Result<&'static Track> {
+    let track = Track::load(config)?;
+    info!(
+        "Loaded track {} with {} nodes",
+        config.get_path().display(),
+        track.get_nodes().len() - 1
+    );
+
+    TRACK.set(track).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "Track has already been loaded",
+        )
+    })?;
+
+    Ok(get_track())
+}
 
-    Ok(path)
+pub fn get_track() -> &'static Track {
+    TRACK
+        .get()
+        .expect("Track has not been loaded, call track::init_track first")
 }
 
-fn fast_euclidean_distance(node1: &TrackNode, node2: &TrackNode) -> f32 {
-    (node1.x - node2.x).powf(2_f32) + (node1.y - node2.y).powf(2_f32)
+#[cfg(test)]
+pub fn get_test_track() -> &'static Track {
+    static TEST_TRACK: OnceLock<Track> = OnceLock::new();
+
+    TEST_TRACK.get_or_init(|| {
+        Track::load(&TrackConfig {
+            map: format!("{TRACKS_DIR}/test_track.json"),
+            height: 6.0,
+            annotations: None,
+        })
+        .unwrap()
+    })
+}
+
+/// Every map in [TRACKS_DIR], with its file name
+#[cfg(test)]
+pub fn load_bundled_tracks() -> Vec<(String, Track)> {
+    let mut tracks: Vec<(String, Track)> = std::fs::read_dir(TRACKS_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.to_string_lossy().ends_with(".annotations.json"))
+        .map(|path| {
+            let config = TrackConfig {
+                map: path.to_string_lossy().to_string(),
+                ..Default::default()
+            };
+            let track = Track::load(&config).unwrap_or_else(|e| panic!("{e}"));
+            (
+                path.file_name().unwrap().to_string_lossy().to_string(),
+                track,
+            )
+        })
+        .collect();
+    tracks.sort_by(|a, b| a.0.cmp(&b.0));
+    tracks
 }
 
 #[cfg(test)]
@@ -116,20 +177,33 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_find_path() {
-        let track = get_track();
-        let start_node = match track.get_node_by_id(24) {
-            Some(node) => node,
-            None => panic!("start node not found"),
+    fn test_track_config() {
+        let by_name = TrackConfig {
+            map: "test_track".to_string(),
+            height: 6.0,
+            annotations: None,
         };
-
-        let end_node = match track.get_node_by_id(60) {
-            Some(node) => node,
-            None => panic!("end node not found"),
+        assert_eq!(
+            by_name.get_path(),
+            Path::new(TRACKS_DIR).join("test_track.graphml")
+        );
+        assert!(Track::load(&by_name).is_ok());
+
+        let missing = TrackConfig {
+            map: "res/tracks/missing_track.graphml".to_string(),
+            height: 6.0,
+            annotations: None,
         };
+        assert!(Track::load(&missing).is_err());
+    }
 
-        let path = find_path(track, start_node, end_node).unwrap();
+    #[test]
+    fn test_load_bundled_maps() {
+        let tracks = load_bundled_tracks();
+        for (name, track) in &tracks {
+            assert!(track.get_nodes().len() > 1, "{name} has no nodes");
+        }
 
-        assert_eq!(path.len(), 6);
+        assert_eq!(tracks.len(), 2);
     }
 }
@@ -1,6 +1,16 @@
-use crate::track::data::{Track, TrackEdge, TrackNode};
+use std::io;
+use std::path::Path;
+
+use roxmltree::{Document, Node};
 use serde::Deserialize;
 
+use crate::track::data::{Track, TrackEdge, TrackNode};
+
+/// GraphML data keys used by the competition maps
+const GRAPHML_X_KEY: &str = "d0";
+const GRAPHML_Y_KEY: &str = "d1";
+const GRAPHML_DOTTED_KEY: &str = "d2";
+
 #[derive(Clone, Deserialize)]
 struct ParsingNode {
     pub id: usize,
@@ -21,11 +31,168 @@ struct NodesAndEdges {
     pub edges: Vec<ParsingEdge>,
 }
 
-pub fn parse_track(path: &str, y_axis_size: f32) -> std::io::Result<Track> {
+fn invalid_data<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/**
+ * Parses a track from either the official `.graphml` map or its JSON equivalent,
+ * depending on the file extension.
+ */
+pub fn parse_track(path: &str, y_axis_size: f32) -> io::Result<Track> {
     let file = std::fs::read_to_string(path)?;
-    let nodes_and_edges: NodesAndEdges = serde_json::from_str(&file)?;
 
+    let nodes_and_edges = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("graphml") => parse_graphml(&file)?,
+        _ => serde_json::from_str(&file)?,
+    };
+
+    build_track(nodes_and_edges, y_axis_size)
+}
+
+fn parse_graphml(content: &str) -> io::Result<NodesAndEdges> {
+    let document =
+        Document::parse(content).map_err(|e| invalid_data(format!("Invalid GraphML: {e}")))?;
+
+    let graph = document
+        .descendants()
+        .find(|node| node.has_tag_name("graph"))
+        .ok_or_else(|| invalid_data("GraphML file does not contain a <graph> element"))?;
+
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    for element in graph.children().filter(Node::is_element) {
+        match element.tag_name().name() {
+            "node" => nodes.push(parse_graphml_node(&document, element)?),
+            "edge" => edges.push(parse_graphml_edge(&document, element)?),
+            _ => {}
+        }
+    }
+
+    Ok(NodesAndEdges { nodes, edges })
+}
+
+fn get_line(document: &Document, element: Node) -> u32 {
+    document.text_pos_at(element.range().start).row
+}
+
+fn get_graphml_data<'a>(element: Node<'a, '_>, key: &str) -> Option<&'a str> {
+    element
+        .children()
+        .find(|child| child.has_tag_name("data") && child.attribute("key") == Some(key))
+        .map(|data| data.text().unwrap_or_default().trim())
+}
+
+fn parse_graphml_id(raw_id: &str) -> Option<usize> {
+    raw_id.trim().parse().ok()
+}
+
+fn parse_graphml_node(document: &Document, element: Node) -> io::Result<ParsingNode> {
+    let line = get_line(document, element);
+
+    let raw_id = element
+        .attribute("id")
+        .ok_or_else(|| invalid_data(format!("Node on line {line} has no id")))?;
+    let id = parse_graphml_id(raw_id).ok_or_else(|| {
+        invalid_data(format!(
+            "Node on line {line} has a non-numeric id \"{raw_id}\""
+        ))
+    })?;
+
+    let parse_coordinate = |key: &str, name: &str| -> io::Result<f32> {
+        let value = get_graphml_data(element, key).ok_or_else(|| {
+            invalid_data(format!(
+                "Node {id} (line {line}) is missing its {name} coordinate ({key})"
+            ))
+        })?;
+
+        value.parse().map_err(|_| {
+            invalid_data(format!(
+                "Node {id} (line {line}) has an invalid {name} coordinate \"{value}\""
+            ))
+        })
+    };
+
+    Ok(ParsingNode {
+        id,
+        x: parse_coordinate(GRAPHML_X_KEY, "x")?,
+        y: parse_coordinate(GRAPHML_Y_KEY, "y")?,
+    })
+}
+
+fn parse_graphml_edge(document: &Document, element: Node) -> io::Result<ParsingEdge> {
+    let line = get_line(document, element);
+
+    let parse_endpoint = |attribute: &str| -> io::Result<usize> {
+        let raw_id = element
+            .attribute(attribute)
+            .ok_or_else(|| invalid_data(format!("Edge on line {line} has no {attribute}")))?;
+
+        parse_graphml_id(raw_id).ok_or_else(|| {
+            invalid_data(format!(
+                "Edge on line {line} has a non-numeric {attribute} \"{raw_id}\""
+            ))
+        })
+    };
+
+    let source = parse_endpoint("source")?;
+    let target = parse_endpoint("target")?;
+
+    let dotted = get_graphml_data(element, GRAPHML_DOTTED_KEY).ok_or_else(|| {
+        invalid_data(format!(
+            "Edge {source} -> {target} (line {line}) is missing its dotted flag ({GRAPHML_DOTTED_KEY})"
+        ))
+    })?;
+
+    let dotted = match dotted.to_lowercase().as_str() {
+        "true" => true,
+        "false" => false,
+        _ => {
+            return Err(invalid_data(format!(
+                "Edge {source} -> {target} (line {line}) has an invalid dotted flag \"{dotted}\""
+            )))
+        }
+    };
+
+    Ok(ParsingEdge {
+        source,
+        target,
+        dotted,
+    })
+}
+
+fn build_track(nodes_and_edges: NodesAndEdges, y_axis_size: f32) -> io::Result<Track> {
     let mut no = nodes_and_edges.nodes;
+    no.sort_by_key(|node| node.id);
+
+    // Nodes are looked up by indexing with their id, so they have to be consecutive
+    for (index, node) in no.iter().enumerate() {
+        let expected_id = index + 1;
+        if node.id < expected_id {
+            return Err(invalid_data(format!(
+                "Node {} is defined more than once",
+                node.id
+            )));
+        }
+        if node.id > expected_id {
+            return Err(invalid_data(format!(
+                "Node ids must be consecutive starting from 1, but node {expected_id} is missing"
+            )));
+        }
+    }
+
+    for edge in &nodes_and_edges.edges {
+        for (endpoint, id) in [("source", edge.source), ("target", edge.target)] {
+            if id == 0 || id > no.len() {
+                return Err(invalid_data(format!(
+                    "Edge {} -> {}: {endpoint} node does not exist",
+                    edge.source, edge.target
+                )));
+            }
+        }
+    }
+
     no.insert(
         0,
         ParsingNode {
@@ -34,7 +201,6 @@ pub fn parse_track(path: &str, y_axis_size: f32) -> std::io::Result<Track> {
             y: 0.0,
         },
     );
-    no.sort_by(|a, b| a.id.cmp(&b.id));
 
     let nodes = no
         .iter()
@@ -56,5 +222,58 @@ pub fn parse_track(path: &str, y_axis_size: f32) -> std::io::Result<Track> {
         })
         .collect();
 
-    Ok(Track(nodes))
+    Ok(Track::new(nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::TRACKS_DIR;
+
+    use super::*;
+
+    fn parse_graphml_error(content: &str) -> String {
+        parse_graphml(content)
+            .and_then(|nodes_and_edges| build_track(nodes_and_edges, 6.0))
+            .err()
+            .expect("parsing should have failed")
+            .to_string()
+    }
+
+    #[test]
+    fn test_graphml_matches_json() {
+        let json = parse_track(&format!("{TRACKS_DIR}/test_track.json"), 6.0).unwrap();
+        let graphml = parse_track(&format!("{TRACKS_DIR}/test_track.graphml"), 6.0).unwrap();
+
+        assert_eq!(json.get_nodes(), graphml.get_nodes());
+    }
+
+    #[test]
+    fn test_graphml_errors_point_at_element() {
+        let invalid_node = r#"<graphml><graph>
+            <node id="1"><data key="d0">1.0</data><data key="d1">abc</data></node>
+        </graph></graphml>"#;
+        assert_eq!(
+            parse_graphml_error(invalid_node),
+            "Node 1 (line 2) has an invalid y coordinate \"abc\""
+        );
+
+        let missing_dotted = r#"<graphml><graph>
+            <node id="1"><data key="d0">1.0</data><data key="d1">2.0</data></node>
+            <node id="2"><data key="d0">1.0</data><data key="d1">2.5</data></node>
+            <edge source="1" target="2"></edge>
+        </graph></graphml>"#;
+        assert_eq!(
+            parse_graphml_error(missing_dotted),
+            "Edge 1 -> 2 (line 4) is missing its dotted flag (d2)"
+        );
+
+        let unknown_target = r#"<graphml><graph>
+            <node id="1"><data key="d0">1.0</data><data key="d1">2.0</data></node>
+            <edge source="1" target="7"><data key="d2">False</data></edge>
+        </graph></graphml>"#;
+        assert_eq!(
+            parse_graphml_error(unknown_target),
+            "Edge 1 -> 7: target node does not exist"
+        );
+    }
 }
@@ -0,0 +1,428 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::f32::consts::{FRAC_PI_4, PI};
+
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use shared::math::AngleWrap;
+
+use crate::track::blocking::Blockages;
+use crate::track::data::{State, Track, TrackEdge, TrackNode};
+
+/// Extra costs added on top of the length of each edge while searching for a path.
+/// Like the track coordinates, all costs are expressed in centimeters.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PathCosts {
+    /// Taking a dotted edge out of a node with multiple exits, which means switching lanes
+    pub lane_change: f32,
+    /// Taking a solid edge out of a node with multiple exits, which means crossing an intersection
+    pub intersection: f32,
+    /// Turning by more than [PathCosts::sharp_turn_angle] between two consecutive edges
+    pub sharp_turn: f32,
+    /// Heading change, in radians, above which a turn is considered sharp
+    pub sharp_turn_angle: f32,
+    /// Heading change, in radians, above which a turn is a U-turn and is never taken
+    pub max_turn_angle: f32,
+}
+
+impl Default for PathCosts {
+    fn default() -> Self {
+        Self {
+            lane_change: 50.0,
+            intersection: 20.0,
+            sharp_turn: 30.0,
+            sharp_turn_angle: FRAC_PI_4,
+            max_turn_angle: PI * 5.0 / 6.0,
+        }
+    }
+}
+
+impl PathCosts {
+    /// No penalties at all, the shortest path will be found
+    #[allow(dead_code, reason = "preset for callers that only care about distance")]
+    pub fn distance_only() -> Self {
+        Self {
+            lane_change: 0.0,
+            intersection: 0.0,
+            sharp_turn: 0.0,
+            ..Self::default()
+        }
+    }
+
+    /**
+     * Cost of going from `node` to `next` through `edge`, having arrived to `node` from `previous`,
+     * or [None] if that would be a U-turn.
+     * Penalties are never negative, so the cost is at least the length of the edge.
+     */
+    pub fn get_edge_cost(
+        &self,
+        previous: Option<&TrackNode>,
+        node: &TrackNode,
+        edge: &TrackEdge,
+        next: &TrackNode,
+    ) -> Option<f32> {
+        let mut cost = node.distance_to(next);
+
+        if node.edges.len() > 1 {
+            cost += if edge.dotted {
+                self.lane_change.max(0.0)
+            } else {
+                self.intersection.max(0.0)
+            };
+        }
+
+        if let Some(previous) = previous {
+            let turn_angle = get_turn_angle(previous, node, next);
+
+            if turn_angle > self.max_turn_angle {
+                return None;
+            }
+            if turn_angle > self.sharp_turn_angle {
+                cost += self.sharp_turn.max(0.0);
+            }
+        }
+
+        Some(cost)
+    }
+}
+
+/// Absolute heading change when driving `previous` -> `node` -> `next`
+fn get_turn_angle(previous: &TrackNode, node: &TrackNode, next: &TrackNode) -> f32 {
+    // Nodes sharing the same position (like the ones inside intersections) have no heading
+    if previous.distance_to(node) < f32::EPSILON || node.distance_to(next) < f32::EPSILON {
+        return 0.0;
+    }
+
+    let turn = node.heading_to(next) as f64 - previous.heading_to(node) as f64;
+    turn.angle_wrap().abs() as f32
+}
+
+/**
+ * Recomputes the cost of driving through `nodes`, having arrived to the first one from `previous_node`.
+ * Returns [None] if two consecutive nodes are not connected or the path contains a U-turn.
+ */
+pub fn get_path_cost(
+    previous_node: Option<&TrackNode>,
+    nodes: &[&TrackNode],
+    costs: &PathCosts,
+) -> Option<f32> {
+    let mut previous = previous_node;
+    let mut total_cost = 0.0;
+
+    for pair in nodes.windows(2) {
+        let edge = pair[0]
+            .edges
+            .iter()
+            .find(|edge| edge.target == pair[1].id)?;
+        total_cost += costs.get_edge_cost(previous, pair[0], edge, pair[1])?;
+        previous = Some(pair[0]);
+    }
+
+    Some(total_cost)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackPath<'a> {
+    pub nodes: Vec<&'a TrackNode>,
+    pub cost: f32,
+}
+
+/// A search state is the current node together with the one we came from
+type StateKey = (Option<usize>, usize);
+
+#[allow(
+    dead_code,
+    reason = "planning API, missions go through plan_mission instead"
+)]
+pub fn find_path_coords<'a>(
+    track: &'a Track,
+    start_coord: (f32, f32),
+    end_coord: (f32, f32),
+    costs: &PathCosts,
+) -> Result<TrackPath<'a>, String> {
+    let start_node = track
+        .find_closest_node(start_coord.0, start_coord.1)
+        .ok_or_else(|| "Didn't find start node".to_string())?;
+    let end_node = track
+        .find_closest_node(end_coord.0, end_coord.1)
+        .ok_or_else(|| "Didn't find end node".to_string())?;
+
+    find_path(track, start_node, end_node, costs)
+}
+
+#[allow(
+    dead_code,
+    reason = "planning API, missions go through plan_mission instead"
+)]
+pub fn find_path<'a>(
+    track: &'a Track,
+    start_node: &'a TrackNode,
+    end_node: &'a TrackNode,
+    costs: &PathCosts,
+) -> Result<TrackPath<'a>, String> {
+    find_path_from(track, None, start_node, end_node, costs)
+}
+
+pub fn find_path_from<'a>(
+    track: &'a Track,
+    previous_node: Option<&'a TrackNode>,
+    start_node: &'a TrackNode,
+    end_node: &'a TrackNode,
+    costs: &PathCosts,
+) -> Result<TrackPath<'a>, String> {
+    find_path_avoiding(
+        track,
+        &Blockages::default(),
+        previous_node,
+        start_node,
+        end_node,
+        costs,
+    )
+}
+
+/**
+ * Weighted A* over the track graph. Since turn penalties depend on the direction we arrived from,
+ * the search runs over (previous node, node) pairs, with the straight line distance as heuristic.
+ *
+ * `previous_node` is the node the car is coming from when it reaches `start_node`, if known,
+ * so that the path continues in the same direction. It is not part of the returned path.
+ * Blocked edges and nodes are never used.
+ */
+pub fn find_path_avoiding<'a>(
+    track: &'a Track,
+    blockages: &Blockages,
+    previous_node: Option<&'a TrackNode>,
+    start_node: &'a TrackNode,
+    end_node: &'a TrackNode,
+    costs: &PathCosts,
+) -> Result<TrackPath<'a>, String> {
+    let start_key: StateKey = (previous_node.map(|node| node.id), start_node.id);
+
+    let mut prio_queue = BinaryHeap::new();
+    prio_queue.push(State {
+        cost: OrderedFloat(start_node.distance_to(end_node)),
+        node: start_node,
+        previous: previous_node,
+    });
+
+    let mut came_from: HashMap<StateKey, StateKey> = HashMap::new();
+    let mut cost_so_far: HashMap<StateKey, f32> = HashMap::new();
+    cost_so_far.insert(start_key, 0.0);
+
+    while let Some(State {
+        cost: priority,
+        node,
+        previous,
+    }) = prio_queue.pop()
+    {
+        let key = (previous.map(|previous| previous.id), node.id);
+        let cost = cost_so_far[&key];
+
+        // A cheaper way to reach this state was found after this entry was queued
+        if priority.0 > cost + node.distance_to(end_node) {
+            continue;
+        }
+
+        if node == end_node {
+            return Ok(TrackPath {
+                nodes: reconstruct_path(track, &came_from, start_key, key),
+                cost,
+            });
+        }
+
+        for edge in &node.edges {
+            let next_node = track
+                .get_node_by_id(edge.target)
+                .ok_or_else(|| format!("track does not contain node with id {}", edge.target))?;
+
+            if blockages.is_edge_blocked(node.id, next_node.id)
+                || blockages.is_node_blocked(next_node.id)
+            {
+                continue;
+            }
+
+            let Some(edge_cost) = costs.get_edge_cost(previous, node, edge, next_node) else {
+                continue;
+            };
+            let new_cost = cost + edge_cost;
+            let next_key = (Some(node.id), next_node.id);
+
+            let is_cheaper = match cost_so_far.get(&next_key) {
+                Some(&next_cost) => new_cost < next_cost,
+                None => true,
+            };
+
+            if is_cheaper {
+                cost_so_far.insert(next_key, new_cost);
+                came_from.insert(next_key, key);
+
+                prio_queue.push(State {
+                    cost: OrderedFloat(new_cost + next_node.distance_to(end_node)),
+                    node: next_node,
+                    previous: Some(node),
+                });
+            }
+        }
+    }
+
+    Err("No path found".to_string())
+}
+
+fn reconstruct_path<'a>(
+    track: &'a Track,
+    came_from: &HashMap<StateKey, StateKey>,
+    start_key: StateKey,
+    end_key: StateKey,
+) -> Vec<&'a TrackNode> {
+    let mut path = Vec::new();
+    let mut current_key = end_key;
+
+    while current_key != start_key {
+        path.push(current_key.1);
+        current_key = match came_from.get(&current_key) {
+            Some(key) => *key,
+            _ => panic!("came_from does not contain node"),
+        };
+    }
+
+    path.push(start_key.1);
+    path.reverse();
+
+    path.into_iter()
+        .map(|id| {
+            track
+                .get_node_by_id(id)
+                .expect("path contains unknown node")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{get_test_track, load_bundled_tracks};
+
+    use super::*;
+
+    /// Brute force Dijkstra from `start_node`, returning the cost to reach every node
+    fn dijkstra_costs(track: &Track, start_node: &TrackNode, costs: &PathCosts) -> Vec<f32> {
+        let mut best: HashMap<StateKey, f32> = HashMap::from([((None, start_node.id), 0.0)]);
+        let mut visited = Vec::new();
+
+        loop {
+            let next = best
+                .iter()
+                .filter(|(key, _)| !visited.contains(*key))
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(key, cost)| (*key, *cost));
+
+            let Some((key, cost)) = next else {
+                break;
+            };
+            visited.push(key);
+
+            let previous = key.0.and_then(|id| track.get_node_by_id(id));
+            let node = track.get_node_by_id(key.1).unwrap();
+
+            for edge in &node.edges {
+                let next_node = track.get_node_by_id(edge.target).unwrap();
+                let Some(edge_cost) = costs.get_edge_cost(previous, node, edge, next_node) else {
+                    continue;
+                };
+                let new_cost = cost + edge_cost;
+                let entry = best
+                    .entry((Some(node.id), next_node.id))
+                    .or_insert(f32::MAX);
+                *entry = entry.min(new_cost);
+            }
+        }
+
+        let mut node_costs = vec![f32::INFINITY; track.get_nodes().len()];
+        for ((_, id), cost) in best {
+            node_costs[id] = node_costs[id].min(cost);
+        }
+        node_costs
+    }
+
+    #[test]
+    fn test_find_path() {
+        let track = get_test_track();
+        let start_node = match track.get_node_by_id(24) {
+            Some(node) => node,
+            None => panic!("start node not found"),
+        };
+
+        let end_node = match track.get_node_by_id(60) {
+            Some(node) => node,
+            None => panic!("end node not found"),
+        };
+
+        let path = find_path(track, start_node, end_node, &PathCosts::default()).unwrap();
+
+        assert_eq!(path.nodes.len(), 6);
+    }
+
+    #[test]
+    fn test_path_cost_is_length_without_penalties() {
+        let track = get_test_track();
+        let start_node = track.get_node_by_id(32).unwrap();
+        let end_node = track.get_node_by_id(36).unwrap();
+
+        let path = find_path(track, start_node, end_node, &PathCosts::distance_only()).unwrap();
+        let length: f32 = path
+            .nodes
+            .windows(2)
+            .map(|nodes| nodes[0].distance_to(nodes[1]))
+            .sum();
+
+        assert_eq!(path.nodes.len(), 5);
+        assert!((path.cost - length).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_matches_dijkstra() {
+        for (name, track) in load_bundled_tracks() {
+            check_matches_dijkstra(&name, &track);
+        }
+    }
+
+    fn check_matches_dijkstra(name: &str, track: &Track) {
+        let all_costs = [
+            PathCosts::distance_only(),
+            PathCosts::default(),
+            PathCosts {
+                lane_change: 500.0,
+                intersection: 300.0,
+                sharp_turn: 1000.0,
+                sharp_turn_angle: 0.3,
+                max_turn_angle: 1.8,
+            },
+        ];
+
+        for costs in &all_costs {
+            for start_node in &track.get_nodes()[1..] {
+                let expected_costs = dijkstra_costs(track, start_node, costs);
+
+                for end_node in &track.get_nodes()[1..] {
+                    let expected_cost = expected_costs[end_node.id];
+
+                    match find_path(track, start_node, end_node, costs) {
+                        Ok(path) => {
+                            assert!(
+                                (path.cost - expected_cost).abs() < 1e-2,
+                                "{name}, {} -> {}: A* cost {} but Dijkstra cost {expected_cost}",
+                                start_node.id,
+                                end_node.id,
+                                path.cost
+                            );
+                            let recomputed_cost = get_path_cost(None, &path.nodes, costs).unwrap();
+                            assert!((recomputed_cost - path.cost).abs() < 1e-2);
+                            assert_eq!(path.nodes.first(), Some(&start_node));
+                            assert_eq!(path.nodes.last(), Some(&end_node));
+                        }
+                        Err(_) => assert!(expected_cost.is_infinite()),
+                    }
+                }
+            }
+        }
+    }
+}
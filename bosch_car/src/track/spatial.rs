@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use shared::math::{AngleWrap, Point};
+
+use crate::track::data::{Track, TrackEdge, TrackNode};
+
+/// Size of a grid cell, in the track's units (centimeters)
+const CELL_SIZE: f32 = 50.0;
+/// Only edges this close to the car are considered when matching its position to the map
+const MAP_MATCHING_RADIUS: f32 = 60.0;
+/// How many centimeters of distance a radian of heading difference is worth when map matching
+const MAP_MATCHING_HEADING_WEIGHT: f32 = 40.0;
+
+type Cell = (i32, i32);
+
+#[derive(Debug, Default)]
+struct CellContents {
+    nodes: Vec<usize>,
+    /// Edges passing through the cell as (source, target)
+    edges: Vec<(usize, usize)>,
+}
+
+/// Uniform grid over the nodes and edges of the track, used to speed up proximity queries
+#[derive(Debug, Default)]
+pub struct SpatialGrid {
+    cells: HashMap<Cell, CellContents>,
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+fn get_cell(x: f32, y: f32) -> Cell {
+    (
+        (x / CELL_SIZE).floor() as i32,
+        (y / CELL_SIZE).floor() as i32,
+    )
+}
+
+impl SpatialGrid {
+    pub fn new(nodes: &[TrackNode]) -> SpatialGrid {
+        let mut grid = SpatialGrid {
+            cells: HashMap::new(),
+            min_cell: (i32::MAX, i32::MAX),
+            max_cell: (i32::MIN, i32::MIN),
+        };
+
+        // The first node is a placeholder
+        for node in nodes.iter().skip(1) {
+            let cell = get_cell(node.get_x(), node.get_y());
+            grid.cells.entry(cell).or_default().nodes.push(node.id);
+            grid.include_cell(cell);
+
+            for edge in &node.edges {
+                let Some(target) = nodes.get(edge.target) else {
+                    continue;
+                };
+                let (start_x, start_y) = get_cell(node.get_x(), node.get_y());
+                let (end_x, end_y) = get_cell(target.get_x(), target.get_y());
+
+                // Edges are short, so adding them to every cell of their bounding box is enough
+                for cell_x in start_x.min(end_x)..=start_x.max(end_x) {
+                    for cell_y in start_y.min(end_y)..=start_y.max(end_y) {
+                        grid.cells
+                            .entry((cell_x, cell_y))
+                            .or_default()
+                            .edges
+                            .push((node.id, edge.target));
+                        grid.include_cell((cell_x, cell_y));
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn include_cell(&mut self, (x, y): Cell) {
+        self.min_cell = (self.min_cell.0.min(x), self.min_cell.1.min(y));
+        self.max_cell = (self.max_cell.0.max(x), self.max_cell.1.max(y));
+    }
+
+    /// Contents of the cells at exactly `ring` cells away from `center`
+    fn get_ring(
+        &self,
+        (center_x, center_y): Cell,
+        ring: i32,
+    ) -> impl Iterator<Item = &CellContents> {
+        (center_x - ring..=center_x + ring)
+            .flat_map(move |x| (center_y - ring..=center_y + ring).map(move |y| (x, y)))
+            .filter(move |(x, y)| (x - center_x).abs() == ring || (y - center_y).abs() == ring)
+            .filter_map(|cell| self.cells.get(&cell))
+    }
+
+    /// Number of rings needed from `center` to cover the whole grid
+    fn get_max_ring(&self, (x, y): Cell) -> i32 {
+        [
+            x - self.min_cell.0,
+            self.max_cell.0 - x,
+            y - self.min_cell.1,
+            self.max_cell.1 - y,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+        .max(0)
+    }
+
+    /**
+     * Searches the rings around the point until no closer item can exist,
+     * returning the item with the smallest distance among those returned by `get_items`.
+     */
+    fn find_closest<T, F>(&self, x: f32, y: f32, get_items: F) -> Option<(T, f32)>
+    where
+        F: Fn(&CellContents) -> Vec<(T, f32)>,
+    {
+        if !x.is_finite() || !y.is_finite() || self.cells.is_empty() {
+            return None;
+        }
+
+        let center = get_cell(x, y);
+        let mut best: Option<(T, f32)> = None;
+
+        for ring in 0..=self.get_max_ring(center) {
+            for (item, distance) in self.get_ring(center, ring).flat_map(&get_items) {
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best_distance)| distance < *best_distance)
+                {
+                    best = Some((item, distance));
+                }
+            }
+
+            // Every cell of the next ring is at least this far away
+            if best
+                .as_ref()
+                .is_some_and(|(_, distance)| *distance <= ring as f32 * CELL_SIZE)
+            {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Edges passing through any cell within `radius` of the point
+    fn get_edges_near(&self, x: f32, y: f32, radius: f32) -> Vec<(usize, usize)> {
+        let (min_x, min_y) = get_cell(x - radius, y - radius);
+        let (max_x, max_y) = get_cell(x + radius, y + radius);
+
+        let mut edges: Vec<_> = (min_x..=max_x)
+            .flat_map(|cell_x| (min_y..=max_y).map(move |cell_y| (cell_x, cell_y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flat_map(|contents| contents.edges.iter().copied())
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        edges
+    }
+}
+
+/// The point of an edge closest to a given position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeProjection<'a> {
+    pub source: &'a TrackNode,
+    pub target: &'a TrackNode,
+    pub edge: &'a TrackEdge,
+    pub x: f32,
+    pub y: f32,
+    /// How far along the edge the projection is, from 0 (source) to 1 (target)
+    pub ratio: f32,
+    /// Distance from the position to its projection
+    pub distance: f32,
+    /// Direction of travel along the edge, in radians
+    pub heading: f32,
+}
+
+/**
+ * The point of the segment from `start` to `end` closest to `point`, and how far along the segment
+ * it is, from 0 (start) to 1 (end)
+ */
+pub fn project_on_segment(point: Point, start: Point, end: Point) -> (Point, f64) {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let ratio = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+
+    (
+        Point::new(start.x + dx * ratio, start.y + dy * ratio),
+        ratio,
+    )
+}
+
+impl Track {
+    fn project_on_edge(
+        &self,
+        source: usize,
+        target: usize,
+        x: f32,
+        y: f32,
+    ) -> Option<EdgeProjection<'_>> {
+        let source = self.get_node_by_id(source)?;
+        let edge = source.edges.iter().find(|edge| edge.target == target)?;
+        let target = self.get_node_by_id(target)?;
+
+        // In centimeters, like the nodes
+        let to_point = |x: f32, y: f32| Point::new(x as f64, y as f64);
+        let position = to_point(x, y);
+        let (projection, ratio) = project_on_segment(
+            position,
+            to_point(source.get_x(), source.get_y()),
+            to_point(target.get_x(), target.get_y()),
+        );
+
+        Some(EdgeProjection {
+            source,
+            target,
+            edge,
+            x: projection.x as f32,
+            y: projection.y as f32,
+            ratio: ratio as f32,
+            distance: position.distance_to(projection) as f32,
+            heading: source.heading_to(target),
+        })
+    }
+
+    pub fn find_closest_node(&self, x: f32, y: f32) -> Option<&TrackNode> {
+        self.grid
+            .find_closest(x, y, |contents| {
+                contents
+                    .nodes
+                    .iter()
+                    .filter_map(|&id| self.get_node_by_id(id))
+                    .map(|node| (node, (x - node.get_x()).hypot(y - node.get_y())))
+                    .collect()
+            })
+            .map(|(node, _)| node)
+    }
+
+    /**
+     * Finds the edge passing closest to the given point, together with the projection of the point on it
+     */
+    pub fn find_closest_edge(&self, x: f32, y: f32) -> Option<EdgeProjection<'_>> {
+        self.grid
+            .find_closest(x, y, |contents| {
+                contents
+                    .edges
+                    .iter()
+                    .filter_map(|&(source, target)| self.project_on_edge(source, target, x, y))
+                    .map(|projection| (projection, projection.distance))
+                    .collect()
+            })
+            .map(|(projection, _)| projection)
+    }
+
+    /**
+     * Finds the edge the car is most likely driving on, using both its position and heading (in radians),
+     * so that on two-way roads the lane going in the car's direction is chosen.
+     * Edges going in a direction more than 90 degrees off from the car's heading are never matched.
+     */
+    pub fn match_position(&self, x: f32, y: f32, heading: f32) -> Option<EdgeProjection<'_>> {
+        if !heading.is_finite() {
+            return None;
+        }
+
+        self.grid
+            .get_edges_near(x, y, MAP_MATCHING_RADIUS)
+            .into_iter()
+            .filter_map(|(source, target)| self.project_on_edge(source, target, x, y))
+            .filter(|projection| projection.distance <= MAP_MATCHING_RADIUS)
+            .filter_map(|projection| {
+                let heading_error =
+                    ((projection.heading - heading) as f64).angle_wrap().abs() as f32;
+                (heading_error <= FRAC_PI_2).then_some((
+                    projection,
+                    projection.distance + MAP_MATCHING_HEADING_WEIGHT * heading_error,
+                ))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(projection, _)| projection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use crate::track::get_test_track;
+
+    use super::*;
+
+    #[test]
+    fn test_grid_matches_linear_search() {
+        let track = get_test_track();
+        let nodes = &track.get_nodes()[1..];
+
+        for x in (-100..700).step_by(17) {
+            for y in (-100..700).step_by(17) {
+                let (x, y) = (x as f32, y as f32);
+
+                let closest_node = track.find_closest_node(x, y).unwrap();
+                let expected_distance = nodes
+                    .iter()
+                    .map(|node| (x - node.get_x()).hypot(y - node.get_y()))
+                    .min_by(f32::total_cmp)
+                    .unwrap();
+                let distance = (x - closest_node.get_x()).hypot(y - closest_node.get_y());
+                assert!((distance - expected_distance).abs() < 1e-3);
+
+                let closest_edge = track.find_closest_edge(x, y).unwrap();
+                let expected_distance = nodes
+                    .iter()
+                    .flat_map(|node| node.edges.iter().map(move |edge| (node.id, edge.target)))
+                    .filter_map(|(source, target)| track.project_on_edge(source, target, x, y))
+                    .map(|projection| projection.distance)
+                    .min_by(f32::total_cmp)
+                    .unwrap();
+                assert!((closest_edge.distance - expected_distance).abs() < 1e-3);
+            }
+        }
+
+        assert!(track.find_closest_node(f32::NAN, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_match_position_uses_heading() {
+        let track = get_test_track();
+        let get_edge = |projection: EdgeProjection| (projection.source.id, projection.target.id);
+
+        // Straight two-way road, the opposite lane being on the left
+        let source = track.get_node_by_id(33).unwrap();
+        let target = track.get_node_by_id(34).unwrap();
+        let heading = source.heading_to(target);
+        let (left_x, left_y) = (-heading.sin(), heading.cos());
+        let x = (source.get_x() + target.get_x()) / 2.0;
+        let y = (source.get_y() + target.get_y()) / 2.0;
+
+        let opposite = track
+            .find_closest_edge(x + left_x * 37.0, y + left_y * 37.0)
+            .unwrap();
+        let opposite_heading = ((opposite.heading - heading) as f64).angle_wrap().abs();
+        assert!((opposite_heading - PI as f64).abs() < 0.1);
+
+        // Between the lanes, closer to the car's own, each heading matches its own lane
+        let (x, y) = (x + left_x * 15.0, y + left_y * 15.0);
+        let matched = track.match_position(x, y, heading).unwrap();
+        assert_eq!(get_edge(matched), (33, 34));
+        let matched = track.match_position(x, y, heading + PI).unwrap();
+        assert_eq!(get_edge(matched), get_edge(opposite));
+
+        // Even closer to the other lane, slightly turned towards it
+        let (x, y) = (x + left_x * 10.0, y + left_y * 10.0);
+        let matched = track.match_position(x, y, heading + 0.3).unwrap();
+        assert_eq!(get_edge(matched), (33, 34));
+    }
+}
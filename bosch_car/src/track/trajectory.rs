@@ -0,0 +1,69 @@
+use shared::math::{Point, Trajectory, TrajectoryConfig};
+
+use crate::track::data::TrackNode;
+
+/// Track coordinates are in centimeters, while trajectories are in meters
+impl From<&TrackNode> for Point {
+    fn from(node: &TrackNode) -> Self {
+        Point::new(node.get_x() as f64 / 100.0, node.get_y() as f64 / 100.0)
+    }
+}
+
+/**
+ * Turns a path through the track into a smooth trajectory the car can follow
+ */
+pub fn build_trajectory(nodes: &[&TrackNode], config: &TrajectoryConfig) -> Trajectory {
+    let waypoints: Vec<Point> = nodes.iter().map(|&node| Point::from(node)).collect();
+    Trajectory::from_waypoints(&waypoints, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::track::{find_path, get_test_track, PathCosts};
+
+    use super::*;
+
+    #[test]
+    fn test_build_trajectory() {
+        let track = get_test_track();
+        let start_node = track.get_node_by_id(24).unwrap();
+        let end_node = track.get_node_by_id(90).unwrap();
+        let path = find_path(track, start_node, end_node, &PathCosts::default()).unwrap();
+
+        let config = TrajectoryConfig::default();
+        let trajectory = build_trajectory(&path.nodes, &config);
+
+        let first = trajectory.points.first().unwrap();
+        let last = trajectory.points.last().unwrap();
+        assert_eq!(first.position, Point::from(start_node));
+        assert_eq!(last.position, Point::from(end_node));
+        assert_eq!(last.speed, 0.0);
+
+        for pair in trajectory.points.windows(2) {
+            let distance = pair[0].position.distance_to(pair[1].position);
+            assert!(
+                distance <= config.spacing * 2.0,
+                "samples are {distance} m apart"
+            );
+
+            // Speeds respect the acceleration limits and the grip available in curves
+            let speed_change = pair[1].speed.powi(2) - pair[0].speed.powi(2);
+            assert!(speed_change <= 2.0 * config.max_acceleration * distance + 1e-6);
+            assert!(-speed_change <= 2.0 * config.max_deceleration * distance + 1e-6);
+            assert!(
+                pair[0].speed.powi(2) * pair[0].curvature.abs()
+                    <= config.max_lateral_acceleration + 1e-6
+            );
+        }
+
+        // The path goes through intersections, so it has to turn somewhere
+        assert!(trajectory
+            .points
+            .iter()
+            .any(|point| point.curvature.abs() > 1.0));
+        assert!(trajectory
+            .points
+            .iter()
+            .all(|point| point.speed <= config.max_speed));
+    }
+}
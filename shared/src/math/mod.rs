@@ -6,13 +6,16 @@ pub use angle_wrap::*;
 pub use circle::*;
 pub use point::*;
 pub use segment::*;
+pub use trajectory::*;
 
 mod almost_equals;
 mod angle_wrap;
 mod circle;
 pub mod pid;
 mod point;
+pub mod pure_pursuit;
 mod segment;
+mod trajectory;
 
 impl From<&CarPosition> for Point {
     fn from(value: &CarPosition) -> Self {
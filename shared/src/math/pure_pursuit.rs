@@ -0,0 +1,191 @@
+use crate::math::{CarPosition, Point, Trajectory};
+
+/// How far ahead of the last closest sample we look for the new closest one, in meters.
+/// Keeps the controller from jumping to another part of the trajectory that passes nearby.
+const SEARCH_DISTANCE: f64 = 1.0;
+
+/// Output of [PurePursuitController::compute]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathCommand {
+    /// Steering angle in radians, positive when turning left, limited to the max steering angle
+    pub steering_angle: f64,
+    /// Recommended speed at the closest point of the trajectory
+    pub speed: f64,
+    /// Index of the trajectory sample closest to the car
+    pub closest_index: usize,
+}
+
+/// Geometric path tracker steering the car towards a point of the trajectory found at a lookahead
+/// distance, which grows with speed so that the car does not oscillate when driving fast.
+/// Distances are in meters and the car's heading is in radians, in the same frame as the trajectory.
+pub struct PurePursuitController {
+    /// Distance between the front and rear axles
+    pub wheelbase: f64,
+    pub min_lookahead: f64,
+    pub max_lookahead: f64,
+    /// Lookahead added for each m/s of speed
+    pub lookahead_gain: f64,
+    pub max_steering_angle: f64,
+
+    last_index: Option<usize>,
+}
+
+impl PurePursuitController {
+    pub fn new(wheelbase: f64) -> Self {
+        Self {
+            wheelbase,
+            min_lookahead: 0.3,
+            max_lookahead: 1.0,
+            lookahead_gain: 1.0,
+            max_steering_angle: 25_f64.to_radians(),
+            last_index: None,
+        }
+    }
+
+    pub fn set_lookahead(mut self, min: f64, max: f64, gain: f64) -> PurePursuitController {
+        assert!(0.0 < min && min <= max);
+        self.min_lookahead = min;
+        self.max_lookahead = max;
+        self.lookahead_gain = gain;
+        self
+    }
+
+    pub fn set_max_steering_angle(mut self, angle: f64) -> PurePursuitController {
+        assert!(angle > 0.0);
+        self.max_steering_angle = angle;
+        self
+    }
+
+    pub fn get_lookahead(&self, speed: f64) -> f64 {
+        (self.min_lookahead + self.lookahead_gain * speed.abs())
+            .clamp(self.min_lookahead, self.max_lookahead)
+    }
+
+    fn find_closest_index(&self, trajectory: &Trajectory, position: Point) -> Option<usize> {
+        let Some(last_index) = self.last_index.filter(|&index| index < trajectory.len()) else {
+            return trajectory.find_closest_index(position);
+        };
+
+        let max_distance = trajectory.points[last_index].distance + SEARCH_DISTANCE;
+
+        trajectory.points[last_index..]
+            .iter()
+            .take_while(|point| point.distance <= max_distance)
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.position
+                    .distance_to(position)
+                    .total_cmp(&b.position.distance_to(position))
+            })
+            .map(|(index, _)| last_index + index)
+    }
+
+    /**
+     * Computes the steering angle needed to reach the lookahead point of the trajectory.
+     * Returns [None] if the trajectory is empty.
+     */
+    pub fn compute(
+        &mut self,
+        trajectory: &Trajectory,
+        position: &CarPosition,
+        speed: f64,
+    ) -> Option<PathCommand> {
+        let car = Point::from(position);
+        let closest_index = self.find_closest_index(trajectory, car)?;
+        self.last_index = Some(closest_index);
+
+        let closest = &trajectory.points[closest_index];
+        let lookahead = self.get_lookahead(speed);
+        let target = trajectory
+            .get_point_at_distance(closest.distance + lookahead)?
+            .position;
+
+        // Angle between the car's heading and the target, as seen from the car
+        let dx = target.x - car.x;
+        let dy = target.y - car.y;
+        let local_x = dx * position.angle.cos() + dy * position.angle.sin();
+        let local_y = dy * position.angle.cos() - dx * position.angle.sin();
+        let target_distance = local_x.hypot(local_y);
+
+        let steering_angle = if target_distance < 1e-6 {
+            0.0
+        } else {
+            let alpha = local_y.atan2(local_x);
+            (2.0 * self.wheelbase * alpha.sin() / target_distance).atan()
+        };
+
+        Some(PathCommand {
+            steering_angle: steering_angle.clamp(-self.max_steering_angle, self.max_steering_angle),
+            speed: closest.speed,
+            closest_index,
+        })
+    }
+
+    /// Should be called whenever the car starts following a new trajectory
+    pub fn reset(&mut self) {
+        self.last_index = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::math::TrajectoryConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_steers_towards_trajectory() {
+        let waypoints: Vec<Point> = (0..20).map(|x| Point::new(x as f64 * 0.3, 0.0)).collect();
+        let trajectory = Trajectory::from_waypoints(&waypoints, &TrajectoryConfig::default());
+        let mut controller = PurePursuitController::new(0.26);
+
+        // On the left of the line, heading along it
+        let left = CarPosition::new(1.0, 0.2, 0.0);
+        let command = controller.compute(&trajectory, &left, 0.3).unwrap();
+        assert!(command.steering_angle < 0.0);
+
+        // On the line but heading to the right of it
+        controller.reset();
+        let turned = CarPosition::new(1.0, 0.0, -0.5);
+        let command = controller.compute(&trajectory, &turned, 0.3).unwrap();
+        assert!(command.steering_angle > 0.0);
+
+        // Perfectly aligned
+        controller.reset();
+        let aligned = CarPosition::new(2.0, 0.0, 0.0);
+        let command = controller.compute(&trajectory, &aligned, 0.3).unwrap();
+        assert!(command.steering_angle.abs() < 1e-6);
+        assert!(command.speed > 0.0);
+
+        assert!(controller.get_lookahead(0.0) < controller.get_lookahead(0.5));
+    }
+    #[test]
+    fn test_limits_steering() {
+        let mut controller =
+            PurePursuitController::new(0.26).set_max_steering_angle(20_f64.to_radians());
+        assert_eq!(
+            controller.compute(
+                &Trajectory::default(),
+                &CarPosition::new(0.0, 0.0, 0.0),
+                0.3
+            ),
+            None
+        );
+
+        let waypoints: Vec<Point> = (0..20).map(|x| Point::new(x as f64 * 0.3, 0.0)).collect();
+        let trajectory = Trajectory::from_waypoints(&waypoints, &TrajectoryConfig::default());
+
+        // Facing away from the trajectory
+        let position = CarPosition::new(1.0, -0.3, -PI / 2.0);
+        let command = controller.compute(&trajectory, &position, 0.3).unwrap();
+        assert!((command.steering_angle - 20_f64.to_radians()).abs() < 1e-9);
+
+        assert_eq!(controller.get_lookahead(10.0), controller.max_lookahead);
+        assert_eq!(
+            controller.get_lookahead(-0.1),
+            controller.min_lookahead + 0.1
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::math::{AngleWrap, Circle, Point};
+
+/// Parameters used when turning a list of waypoints into a [Trajectory].
+/// Distances are in meters and speeds in meters per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryConfig {
+    /// Distance between two consecutive samples of the trajectory
+    pub spacing: f64,
+    pub max_speed: f64,
+    /// Limits the speed in curves, since the lateral acceleration is speed^2 * curvature
+    pub max_lateral_acceleration: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+}
+
+impl Default for TrajectoryConfig {
+    fn default() -> Self {
+        Self {
+            spacing: 0.05,
+            max_speed: 0.5,
+            max_lateral_acceleration: 0.6,
+            max_acceleration: 0.4,
+            max_deceleration: 0.6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TrajectoryPoint {
+    pub position: Point,
+    /// Distance driven from the start of the trajectory
+    pub distance: f64,
+    pub heading: f64, // radians
+    /// Signed inverse of the turning radius, positive when turning left
+    pub curvature: f64,
+    /// Recommended speed when passing through this point
+    pub speed: f64,
+}
+
+/// A smooth curve sampled at roughly regular intervals, which the car can follow
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Trajectory {
+    pub points: Vec<TrajectoryPoint>,
+}
+
+impl Trajectory {
+    /**
+     * Builds a Catmull-Rom spline passing through all the waypoints, computes the curvature
+     * of each sample from the circle passing through it and its neighbours, and limits the speed
+     * so that the car can slow down in time for curves and stops at the last waypoint.
+     */
+    pub fn from_waypoints(waypoints: &[Point], config: &TrajectoryConfig) -> Trajectory {
+        let mut waypoints = waypoints.to_vec();
+        waypoints.dedup_by(|a, b| a.distance_to(*b) < 1e-6);
+
+        let positions = sample_spline(&waypoints, config.spacing);
+        let mut points: Vec<TrajectoryPoint> = positions
+            .iter()
+            .map(|&position| TrajectoryPoint {
+                position,
+                ..Default::default()
+            })
+            .collect();
+
+        let count = points.len();
+        for i in 0..count {
+            let previous = positions[i.saturating_sub(1)];
+            let next = positions[(i + 1).min(count - 1)];
+
+            if i > 0 {
+                points[i].distance =
+                    points[i - 1].distance + positions[i - 1].distance_to(positions[i]);
+            }
+            points[i].heading = (next.y - previous.y).atan2(next.x - previous.x);
+
+            if i > 0 && i + 1 < count {
+                points[i].curvature = get_curvature(previous, positions[i], next);
+            }
+        }
+
+        // The ends have no neighbours on one side, so they share the curvature of the next sample
+        if count > 2 {
+            points[0].curvature = points[1].curvature;
+            points[count - 1].curvature = points[count - 2].curvature;
+        }
+
+        limit_speeds(&mut points, config);
+
+        Trajectory { points }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn get_length(&self) -> f64 {
+        self.points.last().map_or(0.0, |point| point.distance)
+    }
+
+    /// Index of the sample closest to the given position
+    pub fn find_closest_index(&self, position: Point) -> Option<usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.position
+                    .distance_to(position)
+                    .total_cmp(&b.position.distance_to(position))
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// First sample that is at least `distance` away from the start, or the last one
+    pub fn get_point_at_distance(&self, distance: f64) -> Option<&TrajectoryPoint> {
+        let index = self
+            .points
+            .partition_point(|point| point.distance < distance);
+        self.points.get(index).or(self.points.last())
+    }
+}
+
+fn catmull_rom(p0: Point, p1: Point, p2: Point, p3: Point, t: f64) -> Point {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let interpolate = |a: f64, b: f64, c: f64, d: f64| {
+        0.5 * (2.0 * b
+            + (c - a) * t
+            + (2.0 * a - 5.0 * b + 4.0 * c - d) * t2
+            + (3.0 * b - a - 3.0 * c + d) * t3)
+    };
+
+    Point::new(
+        interpolate(p0.x, p1.x, p2.x, p3.x),
+        interpolate(p0.y, p1.y, p2.y, p3.y),
+    )
+}
+
+fn sample_spline(waypoints: &[Point], spacing: f64) -> Vec<Point> {
+    if waypoints.len() < 2 {
+        return waypoints.to_vec();
+    }
+
+    let last = waypoints.len() - 1;
+    // Extend the ends in a straight line so the spline also covers the first and last segments
+    let get_waypoint = |index: isize| -> Point {
+        if index < 0 {
+            let (a, b) = (waypoints[0], waypoints[1]);
+            Point::new(2.0 * a.x - b.x, 2.0 * a.y - b.y)
+        } else if index as usize > last {
+            let (a, b) = (waypoints[last], waypoints[last - 1]);
+            Point::new(2.0 * a.x - b.x, 2.0 * a.y - b.y)
+        } else {
+            waypoints[index as usize]
+        }
+    };
+
+    let mut samples = Vec::new();
+    for i in 0..last as isize {
+        let (p0, p1, p2, p3) = (
+            get_waypoint(i - 1),
+            get_waypoint(i),
+            get_waypoint(i + 1),
+            get_waypoint(i + 2),
+        );
+        let steps = (p1.distance_to(p2) / spacing).ceil().max(1.0) as usize;
+
+        samples
+            .extend((0..steps).map(|step| catmull_rom(p0, p1, p2, p3, step as f64 / steps as f64)));
+    }
+    samples.push(waypoints[last]);
+
+    samples
+}
+
+fn get_curvature(previous: Point, point: Point, next: Point) -> f64 {
+    let circle = Circle::find_center(previous, point, next);
+    if !circle.radius.is_finite() || circle.radius == 0.0 {
+        // The points are on a straight line
+        return 0.0;
+    }
+
+    let turn = ((next.y - point.y).atan2(next.x - point.x)
+        - (point.y - previous.y).atan2(point.x - previous.x))
+    .angle_wrap();
+
+    turn.signum() / circle.radius
+}
+
+fn limit_speeds(points: &mut [TrajectoryPoint], config: &TrajectoryConfig) {
+    for point in points.iter_mut() {
+        point.speed = if point.curvature.abs() > 1e-9 {
+            (config.max_lateral_acceleration / point.curvature.abs())
+                .sqrt()
+                .min(config.max_speed)
+        } else {
+            config.max_speed
+        };
+    }
+
+    // The car has to stop at the end of the trajectory
+    if let Some(last) = points.last_mut() {
+        last.speed = 0.0;
+    }
+
+    // v^2 = v0^2 + 2 * a * d, going forward for accelerating and backward for braking
+    for i in 1..points.len() {
+        let distance = points[i].distance - points[i - 1].distance;
+        let reachable =
+            (points[i - 1].speed.powi(2) + 2.0 * config.max_acceleration * distance).sqrt();
+        points[i].speed = points[i].speed.min(reachable);
+    }
+
+    for i in (0..points.len().saturating_sub(1)).rev() {
+        let distance = points[i + 1].distance - points[i].distance;
+        let reachable =
+            (points[i + 1].speed.powi(2) + 2.0 * config.max_deceleration * distance).sqrt();
+        points[i].speed = points[i].speed.min(reachable);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    fn check_speed_limits(trajectory: &Trajectory, config: &TrajectoryConfig) {
+        for point in &trajectory.points {
+            assert!(point.speed.is_finite() && point.speed >= 0.0);
+            assert!(point.speed <= config.max_speed + 1e-9);
+            assert!(
+                point.speed.powi(2) * point.curvature.abs()
+                    <= config.max_lateral_acceleration + 1e-9
+            );
+        }
+
+        for pair in trajectory.points.windows(2) {
+            let distance = pair[1].distance - pair[0].distance;
+            let change = pair[1].speed.powi(2) - pair[0].speed.powi(2);
+            assert!(change <= 2.0 * config.max_acceleration * distance + 1e-9);
+            assert!(-change <= 2.0 * config.max_deceleration * distance + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_passes_through_waypoints() {
+        let waypoints = [
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(2.0, 1.0),
+            Point::new(2.0, 2.5),
+        ];
+        let trajectory = Trajectory::from_waypoints(&waypoints, &TrajectoryConfig::default());
+
+        for waypoint in waypoints {
+            assert!(trajectory
+                .points
+                .iter()
+                .any(|point| point.position.distance_to(waypoint) < 1e-9));
+        }
+        assert_eq!(trajectory.points[0].position, waypoints[0]);
+        assert_eq!(trajectory.points.last().unwrap().position, waypoints[3]);
+        assert_eq!(trajectory.points.last().unwrap().speed, 0.0);
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let waypoints: Vec<Point> = (0..5).map(|x| Point::new(x as f64, x as f64)).collect();
+        let config = TrajectoryConfig::default();
+        let trajectory = Trajectory::from_waypoints(&waypoints, &config);
+
+        for point in &trajectory.points {
+            assert!(point.curvature.abs() < 1e-6);
+            assert!((point.heading - PI / 4.0).abs() < 1e-6);
+        }
+        assert!((trajectory.get_length() - 4.0 * 2_f64.sqrt()).abs() < 1e-6);
+        check_speed_limits(&trajectory, &config);
+        // Far enough from the ends to drive at full speed
+        let middle = trajectory.get_point_at_distance(2.0).unwrap();
+        assert_eq!(middle.speed, config.max_speed);
+    }
+
+    #[test]
+    fn test_circle() {
+        let radius = 2.0;
+        // Counter-clockwise, so turning left
+        let waypoints: Vec<Point> = (0..=24)
+            .map(|step| {
+                let angle = step as f64 * PI / 24.0;
+                Point::new(radius * angle.cos(), radius * angle.sin())
+            })
+            .collect();
+        let config = TrajectoryConfig::default();
+        let trajectory = Trajectory::from_waypoints(&waypoints, &config);
+
+        let count = trajectory.len();
+        for point in &trajectory.points[count / 4..count * 3 / 4] {
+            assert!(
+                (point.curvature - 1.0 / radius).abs() < 0.01,
+                "curvature {}",
+                point.curvature
+            );
+        }
+        check_speed_limits(&trajectory, &config);
+    }
+
+    #[test]
+    fn test_speed_limits_in_tight_turns() {
+        let waypoints = [
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(1.2, 0.2),
+            Point::new(1.2, 1.2),
+            Point::new(0.2, 1.4),
+        ];
+        let config = TrajectoryConfig {
+            max_speed: 1.0,
+            ..Default::default()
+        };
+        let trajectory = Trajectory::from_waypoints(&waypoints, &config);
+
+        check_speed_limits(&trajectory, &config);
+        assert!(trajectory
+            .points
+            .iter()
+            .any(|point| point.speed < config.max_speed / 2.0 && point.distance < 1.5));
+    }
+
+    #[test]
+    fn test_degenerate_waypoints() {
+        let config = TrajectoryConfig::default();
+
+        let single = Trajectory::from_waypoints(&[Point::new(1.0, 2.0)], &config);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single.points[0].speed, 0.0);
+        assert_eq!(single.get_length(), 0.0);
+
+        assert!(Trajectory::from_waypoints(&[], &config).is_empty());
+
+        let duplicated = [
+            Point::new(0.0, 0.0),
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(2.0, 0.0),
+        ];
+        let trajectory = Trajectory::from_waypoints(&duplicated, &config);
+        let deduplicated = Trajectory::from_waypoints(
+            &[
+                Point::new(0.0, 0.0),
+                Point::new(1.0, 0.0),
+                Point::new(2.0, 0.0),
+            ],
+            &config,
+        );
+        assert_eq!(trajectory, deduplicated);
+        for pair in trajectory.points.windows(2) {
+            assert!(pair[1].distance > pair[0].distance);
+        }
+        check_speed_limits(&trajectory, &config);
+    }
+}